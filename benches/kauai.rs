@@ -0,0 +1,48 @@
+//! Run with `cargo bench --features bench`, which builds the bit at a time decoders to compare
+//! against.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use threedeemm_dump::kauai::{self, Level};
+
+/// Data shaped like a chunk's contents: runs of zeros and repeated records among noise.
+fn sample(len: usize) -> Vec<u8> {
+    let mut state = 1u32;
+    let mut next = || {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        state >> 16
+    };
+    let mut data = Vec::with_capacity(len);
+    while data.len() < len {
+        match next() % 4 {
+            0 => data.resize(data.len() + (next() % 64) as usize, 0),
+            1 if data.len() > 512 => {
+                let start = data.len() - 1 - (next() % 512) as usize;
+                let end = (start + 4 + (next() % 60) as usize).min(data.len());
+                data.extend_from_within(start..end);
+            }
+            _ => data.extend((0..(next() % 32)).map(|_| next() as u8)),
+        }
+    }
+    data.truncate(len);
+    data
+}
+
+fn decode(c: &mut Criterion) {
+    let input = sample(1 << 20);
+    let mut group = c.benchmark_group("decode");
+    group.throughput(Throughput::Bytes(input.len() as u64));
+    for codec in [b"KCDC", b"KCD2"] {
+        let packed = kauai::encode_with_level(codec, &input, Level::default()).unwrap();
+        let name = String::from_utf8_lossy(codec);
+        group.bench_with_input(BenchmarkId::new("words", &name), &packed, |b, packed| {
+            b.iter(|| kauai::decode(packed).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("bitwise", &name), &packed, |b, packed| {
+            b.iter(|| kauai::decode_bitwise(packed).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, decode);
+criterion_main!(benches);
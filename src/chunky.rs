@@ -1,16 +1,51 @@
-use std::{borrow::Cow, cmp::Ordering, collections::HashMap, fmt, mem};
-
-use anyhow::{bail, ensure, Context, Result};
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    fmt, mem,
+    ops::Range,
+    str::FromStr,
+    sync::Arc,
+};
+
+use anyhow::{bail, ensure, Context};
 use bitflags::bitflags;
 use byteorder::{ByteOrder, NativeEndian, ReadBytesExt};
+use encoding_rs::{Encoding, MACINTOSH, WINDOWS_1252};
 use widestring::U16String;
 use zerocopy::{FromBytes, U16, U32};
 
-use crate::{kauai, order::Loader};
+use crate::{
+    error::{Error, Result},
+    kauai,
+    order::Loader,
+};
+
+pub use cache::{ChunkCache, ChunkData};
+pub use writer::{ChunkyWriter, NewChunk};
+
+mod cache;
+#[cfg(test)]
+pub(crate) mod testing;
+mod writer;
 
 const CURRENT_VERSION: u16 = 5;
+const BACKWARDS_VERSION: u16 = 4;
 const MINIMUM_VERSION: u16 = 1;
 
+const MAGIC: &[u8; 4] = b"CHN2";
+/// String kinds, which say how a name is encoded.
+/// Single byte names are in the code page of the system that wrote them.
+const OSK_MAC: u16 = 0x0201;
+const OSK_WINDOWS: u16 = 0x0303;
+const OSK_MAC_UNICODE: u16 = 0x0404;
+const OSK_WINDOWS_UNICODE: u16 = 0x0505;
+
+/// Whether names of string kind `osk` are UTF-16 rather than in a single byte code page.
+pub fn is_unicode_osk(osk: u16) -> bool {
+    matches!(osk, OSK_MAC_UNICODE | OSK_WINDOWS_UNICODE)
+}
+
 #[derive(Debug, FromBytes)]
 #[repr(C)]
 struct DataVersion<O>
@@ -45,7 +80,8 @@ where
     index_offset: U32<O>,
     index_len: U32<O>,
     free_map: U32<O>,
-    reserved: [u8; 23],
+    free_map_len: U32<O>,
+    reserved: [U32<O>; 23],
 }
 
 #[derive(Debug, FromBytes)]
@@ -95,6 +131,47 @@ where
     }
 }
 
+impl<O> fmt::Display for ChunkTag<O>
+where
+    O: ByteOrder,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for b in self.0.get().to_be_bytes() {
+            write!(f, "{}", (b as char).escape_debug())?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ChunkTag {
+    type Err = anyhow::Error;
+
+    /// Parses a tag of up to four characters, padding short tags with spaces.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ensure!(
+            !s.is_empty() && s.len() <= 4 && s.is_ascii(),
+            "Invalid chunk tag {s:?}",
+        );
+        let mut bytes = *b"    ";
+        bytes[..s.len()].copy_from_slice(s.as_bytes());
+        Ok(ChunkTag(U32::new(u32::from_be_bytes(bytes))))
+    }
+}
+
+impl<O> ChunkTag<O>
+where
+    O: ByteOrder,
+{
+    pub fn from_bytes(bytes: [u8; 4]) -> Self {
+        ChunkTag(U32::new(u32::from_be_bytes(bytes)))
+    }
+
+    /// The tag's characters in reading order.
+    pub fn bytes(&self) -> [u8; 4] {
+        self.0.get().to_be_bytes()
+    }
+}
+
 impl<O> PartialEq<&str> for ChunkTag<O>
 where
     O: ByteOrder,
@@ -139,6 +216,35 @@ impl PartialOrd for ChunkId {
     }
 }
 
+impl<O> fmt::Display for ChunkId<O>
+where
+    O: ByteOrder,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.tag, self.number.get())
+    }
+}
+
+impl FromStr for ChunkId {
+    type Err = anyhow::Error;
+
+    /// Parses `TAG:number`, where the number may be decimal or `0x` prefixed hexadecimal.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((tag, number)) = s.rsplit_once(':') else {
+            bail!("Expected TAG:number, got {s:?}");
+        };
+        let number = match number.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => number.parse(),
+        }
+        .with_context(|| format!("Invalid chunk number {number:?}"))?;
+        Ok(ChunkId {
+            tag: tag.parse()?,
+            number: U32::new(number),
+        })
+    }
+}
+
 impl<O> ChunkId<O>
 where
     O: ByteOrder,
@@ -201,6 +307,62 @@ where
     owner_count: U16<O>,
 }
 
+/// Index record used by files with chunks too large or too well connected for
+/// [`ChunkRepresentationSmall`].
+#[derive(Debug, FromBytes)]
+#[repr(C)]
+struct ChunkRepresentationLarge<O>
+where
+    O: ByteOrder,
+{
+    id: ChunkId<O>,
+    offset: U32<O>,
+    length: U32<O>,
+    child_count: U32<O>,
+    owner_count: U32<O>,
+    _runtime_id: U32<O>,
+    grfcrp: U32<O>,
+}
+
+/// The parts of either index record representation that we care about.
+struct ChunkRepresentation {
+    id: ChunkId,
+    offset: u32,
+    flags: ChunkFlags,
+    length: u32,
+    child_count: u32,
+}
+
+impl<O> From<ChunkRepresentationSmall<O>> for ChunkRepresentation
+where
+    O: ByteOrder,
+{
+    fn from(value: ChunkRepresentationSmall<O>) -> Self {
+        ChunkRepresentation {
+            id: value.id.swap(),
+            offset: value.offset.get(),
+            flags: ChunkFlags::from_bits_retain(value.grfcrp_cb.grfcrp()),
+            length: value.grfcrp_cb.length(),
+            child_count: value.child_count.get().into(),
+        }
+    }
+}
+
+impl<O> From<ChunkRepresentationLarge<O>> for ChunkRepresentation
+where
+    O: ByteOrder,
+{
+    fn from(value: ChunkRepresentationLarge<O>) -> Self {
+        ChunkRepresentation {
+            id: value.id.swap(),
+            offset: value.offset.get(),
+            flags: ChunkFlags::from_bits_retain(value.grfcrp.get() as u8),
+            length: value.length.get(),
+            child_count: value.child_count.get(),
+        }
+    }
+}
+
 #[derive(Debug, FromBytes)]
 #[repr(C)]
 struct ChildChunkRef<O>
@@ -211,6 +373,7 @@ where
     child_id: U32<O>,
 }
 
+/// The start of a single byte name, which counts its characters in a byte.
 #[derive(Debug, FromBytes)]
 #[repr(C)]
 struct StringHeader<O>
@@ -221,8 +384,19 @@ where
     len: u8,
 }
 
+/// The start of a UTF-16 name, which counts its characters in a `u16`.
+#[derive(Debug, FromBytes)]
+#[repr(C)]
+struct WideStringHeader<O>
+where
+    O: ByteOrder,
+{
+    osk: U16<O>,
+    len: U16<O>,
+}
+
 bitflags! {
-    #[derive(Debug)]
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
     pub struct ChunkFlags: u8 {
         const EXTRA = 0x01;
         const LONER = 0x02;
@@ -232,12 +406,36 @@ bitflags! {
     }
 }
 
+impl fmt::Display for ChunkFlags {
+    /// Writes the flag names separated by ` | `, or `none`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return f.write_str("none");
+        }
+        for (i, (name, _)) in self.iter_names().enumerate() {
+            if i > 0 {
+                f.write_str(" | ")?;
+            }
+            f.write_str(name)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub struct IndexEntry<'a> {
+    pub id: ChunkId,
     pub offset: u32,
     pub flags: ChunkFlags,
     pub length: u32,
     pub name: Cow<'a, str>,
+    /// False if the stored name isn't valid in its encoding, in which case `name` is the best
+    /// we could make of it.
+    pub name_valid: bool,
+    /// The string kind the name is stored with.
+    pub name_osk: u16,
+    /// The name as it is stored on disk, without its header and terminator.
+    pub raw_name: &'a [u8],
     pub children: Vec<ChildLink>,
 }
 
@@ -257,33 +455,164 @@ impl<'a> IndexEntry<'a> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ChildLink {
     pub chunk_id: ChunkId,
     pub child_id: u32,
 }
 
+/// The reverse of a [`ChildLink`]: `chunk_id` owns the chunk as child `child_id`.
+#[derive(Clone, Debug)]
+pub struct OwnerLink {
+    pub chunk_id: ChunkId,
+    pub child_id: u32,
+}
+
 pub struct ChunkyFile<'a> {
+    pub creator: ChunkTag,
     pub data: &'a [u8],
     pub index: HashMap<ChunkId, IndexEntry<'a>>,
+    pub owners: HashMap<ChunkId, Vec<OwnerLink>>,
+    /// Where the index is stored in `data`.
+    pub index_range: Range<usize>,
+    /// Where the free space map is stored in `data`. This is empty if the file has no free space.
+    pub free_map_range: Range<usize>,
+    /// Unpacked chunks kept around for reuse, if enabled with [`ChunkyFile::with_cache`].
+    pub cache: Option<ChunkCache>,
+    /// The code page single byte Windows names are decoded with.
+    pub code_page: &'static Encoding,
 }
 
 impl<'a> ChunkyFile<'a> {
-    pub fn get_chunk(&self, entry: &IndexEntry) -> Result<Cow<'a, [u8]>> {
-        let data = &self.data[entry.offset as usize..entry.offset as usize + entry.length as usize];
-        if entry.flags.contains(ChunkFlags::PACKED) {
-            Ok(Cow::Owned(
-                kauai::decode(data).with_context(|| format!("Unpacking {entry:?}"))?,
-            ))
-        } else {
-            Ok(Cow::Borrowed(data))
+    /// Chunks that list `id` as a child.
+    pub fn owners(&self, id: &ChunkId) -> &[OwnerLink] {
+        self.owners.get(id).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Chunks that are not owned by any other chunk.
+    pub fn roots(&self) -> impl Iterator<Item = &ChunkId> {
+        self.index.keys().filter(|id| !self.owners.contains_key(id))
+    }
+
+    /// The roots that `id` can be reached from, which is `id` itself if nothing owns it.
+    pub fn roots_of(&self, id: &ChunkId) -> Vec<ChunkId> {
+        let mut roots = Vec::new();
+        let mut visited = HashSet::new();
+        let mut pending = vec![*id];
+        while let Some(id) = pending.pop() {
+            if !visited.insert(id) {
+                continue;
+            }
+            let owners = self.owners(&id);
+            if owners.is_empty() {
+                roots.push(id);
+            }
+            pending.extend(owners.iter().map(|o| o.chunk_id));
         }
+        roots.sort_unstable();
+        roots
+    }
+
+    /// Kauai only keeps unowned chunks around if they are marked as loners.
+    pub fn is_orphan(&self, id: &ChunkId) -> bool {
+        self.owners(id).is_empty()
+            && self
+                .index
+                .get(id)
+                .is_some_and(|e| !e.flags.contains(ChunkFlags::LONER))
+    }
+
+    /// Keeps up to `capacity` bytes of unpacked chunks so packed chunks that are used repeatedly
+    /// are only decoded once.
+    pub fn with_cache(mut self, capacity: usize) -> Self {
+        self.cache = Some(ChunkCache::new(capacity));
+        self
+    }
+
+    /// Decodes single byte Windows names with `code_page` instead of Windows-1252, for files from
+    /// localized releases.
+    pub fn with_code_page(mut self, code_page: &'static Encoding) -> Self {
+        for entry in self.index.values_mut() {
+            if entry.name_osk == OSK_WINDOWS {
+                (entry.name, entry.name_valid) = decode_single_byte(entry.raw_name, code_page);
+            }
+        }
+        self.code_page = code_page;
+        self
+    }
+
+    pub fn get_chunk(&self, entry: &IndexEntry) -> Result<ChunkData<'a>> {
+        let data = self.raw_chunk(entry)?;
+        if !entry.flags.contains(ChunkFlags::PACKED) {
+            return Ok(ChunkData::Borrowed(data));
+        }
+        let Some(cache) = &self.cache else {
+            return Ok(ChunkData::Owned(
+                kauai::decode(data).map_err(|e| e.in_chunk(entry.id))?,
+            ));
+        };
+        if let Some(data) = cache.get(&entry.id) {
+            return Ok(ChunkData::Shared(data));
+        }
+        let data: Arc<[u8]> = kauai::decode(data)
+            .map_err(|e| e.in_chunk(entry.id))?
+            .into();
+        cache.insert(entry.id, data.clone());
+        Ok(ChunkData::Shared(data))
+    }
+
+    /// Reads a chunk flagged [`ChunkFlags::FOREST`], which holds a chunky file of its own.
+    pub fn forest(&self, entry: &IndexEntry) -> Result<Forest<'a>> {
+        if !entry.flags.contains(ChunkFlags::FOREST) {
+            return Err(Error::invalid("Not a forest", entry.offset as usize).in_chunk(entry.id));
+        }
+        Ok(Forest {
+            id: entry.id,
+            data: self.get_chunk(entry)?,
+            code_page: self.code_page,
+        })
+    }
+
+    /// The chunk data as it is stored on disk, without unpacking it.
+    pub fn raw_chunk(&self, entry: &IndexEntry) -> Result<&'a [u8]> {
+        let range = entry.offset as usize..entry.offset as usize + entry.length as usize;
+        self.data
+            .get(range)
+            .ok_or_else(|| Error::truncated("chunk data", self.data.len()).in_chunk(entry.id))
+    }
+
+    /// Unpacks and parses a chunk.
+    pub fn load_chunk<T>(&self, entry: &IndexEntry) -> Result<T>
+    where
+        T: for<'b> Loader<'b>,
+    {
+        T::load(&self.get_chunk(entry)?).map_err(|e| e.in_chunk(entry.id))
+    }
+
+    /// Prepares a copy of this file for writing, keeping chunk data as it is stored on disk.
+    pub fn to_writer(&self) -> Result<ChunkyWriter<'a>> {
+        let mut writer = ChunkyWriter::new(self.creator).with_code_page(self.code_page);
+        for (id, entry) in &self.index {
+            writer.insert(
+                *id,
+                NewChunk {
+                    flags: entry.flags,
+                    name: entry.name.clone(),
+                    unicode_name: is_unicode_osk(entry.name_osk),
+                    children: entry.children.clone(),
+                    data: Cow::Borrowed(self.raw_chunk(entry)?),
+                },
+            );
+        }
+        Ok(writer)
     }
 }
 
 impl<'a> Loader<'a> for ChunkyFile<'a> {
     type OnFile<O> = Prefix<O> where O: ByteOrder;
 
+    const BYTE_ORDER_OFFSET: usize = mem::offset_of!(Prefix<NativeEndian>, byte_order);
+
     fn byte_order<O>(on_file: &Self::OnFile<O>) -> u16
     where
         O: ByteOrder,
@@ -295,29 +624,84 @@ impl<'a> Loader<'a> for ChunkyFile<'a> {
     where
         O: ByteOrder,
     {
-        ensure!(
-            &header.magic.get().to_le_bytes() == b"CHN2",
-            "Wrong magic {}",
-            header.magic.get().to_le_bytes().escape_ascii(),
-        );
-        ensure!(
-            header.version.is_readable(CURRENT_VERSION, MINIMUM_VERSION),
-            "Incompatible version",
-        );
+        if &header.magic.get().to_le_bytes() != MAGIC {
+            return Err(Error::invalid(
+                format!(
+                    "Wrong magic {}",
+                    header.magic.get().to_le_bytes().escape_ascii()
+                ),
+                0,
+            ));
+        }
+        if !header.version.is_readable(CURRENT_VERSION, MINIMUM_VERSION) {
+            return Err(Error::unsupported(
+                "file version",
+                mem::offset_of!(Prefix<O>, version),
+            ));
+        }
 
-        let Some(index) = full_input.get(header.index_offset.get() as usize..header.index_offset.get() as usize + header.index_len.get() as usize) else {
-            bail!("Index out of bounds");
+        let index_range = header.index_offset.get() as usize
+            ..header.index_offset.get() as usize + header.index_len.get() as usize;
+        let Some(index) = full_input.get(index_range.clone()) else {
+            return Err(Error::truncated("index", full_input.len()));
         };
-
-        let index = Group::load(index)?.0;
+        let free_map_range = header.free_map.get() as usize
+            ..header.free_map.get() as usize + header.free_map_len.get() as usize;
+
+        let index = Group::load(index)
+            .map_err(|e| e.offset_by(index_range.start))?
+            .0;
+
+        let mut owners: HashMap<ChunkId, Vec<OwnerLink>> = HashMap::new();
+        for (id, entry) in &index {
+            for child in &entry.children {
+                owners.entry(child.chunk_id).or_default().push(OwnerLink {
+                    chunk_id: *id,
+                    child_id: child.child_id,
+                });
+            }
+        }
+        for links in owners.values_mut() {
+            links.sort_unstable_by(|a, b| {
+                a.chunk_id
+                    .cmp(&b.chunk_id)
+                    .then_with(|| a.child_id.cmp(&b.child_id))
+            });
+        }
 
         Ok(ChunkyFile {
+            creator: ChunkTag(U32::new(header.creator.get())),
             data: full_input,
             index,
+            owners,
+            index_range,
+            free_map_range,
+            cache: None,
+            code_page: WINDOWS_1252,
         })
     }
 }
 
+/// A chunk holding a whole chunky file, the way movies embed imported content.
+///
+/// Child links inside a forest only refer to chunks in the same forest, so the file it holds is
+/// read on its own rather than alongside the file containing it.
+pub struct Forest<'a> {
+    pub id: ChunkId,
+    data: ChunkData<'a>,
+    code_page: &'static Encoding,
+}
+
+impl<'a> Forest<'a> {
+    /// Parses the embedded file, decoding names with the code page of the file containing it.
+    /// Forests can themselves contain forests.
+    pub fn file(&self) -> Result<ChunkyFile<'_>> {
+        Ok(ChunkyFile::load(&self.data)
+            .map_err(|e| e.in_chunk(self.id))?
+            .with_code_page(self.code_page))
+    }
+}
+
 struct Group<'a>(HashMap<ChunkId, IndexEntry<'a>>);
 
 impl<'a> Loader<'a> for Group<'a> {
@@ -336,81 +720,290 @@ impl<'a> Loader<'a> for Group<'a> {
     {
         let mut index = HashMap::new();
 
+        let large = match header.cb_fixed.get() as usize {
+            n if n == mem::size_of::<ChunkRepresentationSmall<O>>() => false,
+            n if n == mem::size_of::<ChunkRepresentationLarge<O>>() => true,
+            n => {
+                return Err(Error::unsupported(
+                    format!("index entry size {n}"),
+                    mem::offset_of!(GroupOnFile<O>, cb_fixed),
+                ))
+            }
+        };
+
         let input = &full_input[mem::size_of::<GroupOnFile<O>>()..];
-        for entry in input[header.bv_mac.get() as usize..]
-            .chunks_exact(mem::size_of::<Loc<O>>())
-            .take(header.iloc_mac.get() as usize)
-        {
-            let Some(loc) = Loc::<O>::read_from(entry) else {
-                bail!("EOF in index entries");
+        let Some(locs) = input.get(header.bv_mac.get() as usize..).and_then(|l| {
+            l.get(..header.iloc_mac.get() as usize * mem::size_of::<Loc<O>>())
+        }) else {
+            return Err(Error::truncated("index entries", full_input.len()));
+        };
+        for (i, loc) in locs.chunks_exact(mem::size_of::<Loc<O>>()).enumerate() {
+            let loc = Loc::<O>::read_from(loc).unwrap();
+            let offset = loc.offset.get() as usize;
+            let length = loc.length.get() as usize;
+            let position = mem::size_of::<GroupOnFile<O>>() + offset;
+            let Some(data) = input.get(offset..offset + length) else {
+                return Err(Error::invalid(
+                    format!("Index entry {i} (+{length:#x}) is out of bounds"),
+                    position,
+                ));
             };
+            let entry = read_index_entry::<O>(data, position, large)?;
+            index.insert(entry.id, entry);
+        }
 
-            let mut data = &input
-                [loc.offset.get() as usize..loc.offset.get() as usize + loc.length.get() as usize];
-            let Some(representation) = ChunkRepresentationSmall::<O>::read_from_prefix(data) else {
-                bail!("EOF in chunk representation");
-            };
+        Ok(Group(index))
+    }
+}
 
-            data = &data[mem::size_of::<ChunkRepresentationSmall<O>>()..];
-            let mut children = Vec::with_capacity(representation.child_count.get() as usize);
-            for _ in 0..representation.child_count.get() {
-                let Some(child) = ChildChunkRef::<O>::read_from_prefix(data) else {
-                    bail!("EOF in chunk children");
-                };
-                children.push(ChildLink {
-                    chunk_id: child.chunk_id.swap(),
-                    child_id: child.child_id.get(),
-                });
-                data = &data[mem::size_of::<ChildChunkRef<O>>()..];
-            }
+/// Reads one index entry from `data`, which starts at `position` in the index.
+fn read_index_entry<O>(mut data: &[u8], position: usize, large: bool) -> Result<IndexEntry<'_>>
+where
+    O: ByteOrder,
+{
+    let end = position + data.len();
+    let representation: ChunkRepresentation = if large {
+        let Some(representation) = ChunkRepresentationLarge::<O>::read_from_prefix(data) else {
+            return Err(Error::truncated("chunk representation", end));
+        };
+        data = &data[mem::size_of::<ChunkRepresentationLarge<O>>()..];
+        representation.into()
+    } else {
+        let Some(representation) = ChunkRepresentationSmall::<O>::read_from_prefix(data) else {
+            return Err(Error::truncated("chunk representation", end));
+        };
+        data = &data[mem::size_of::<ChunkRepresentationSmall<O>>()..];
+        representation.into()
+    };
+    let id = representation.id;
+
+    let mut children = Vec::with_capacity(representation.child_count.min(0x10000) as usize);
+    for _ in 0..representation.child_count {
+        let Some(child) = ChildChunkRef::<O>::read_from_prefix(data) else {
+            return Err(Error::truncated("children", end).in_chunk(id));
+        };
+        children.push(ChildLink {
+            chunk_id: child.chunk_id.swap(),
+            child_id: child.child_id.get(),
+        });
+        data = &data[mem::size_of::<ChildChunkRef<O>>()..];
+    }
 
-            children.sort_unstable_by(|a, b| {
-                a.child_id
-                    .cmp(&b.child_id)
-                    .then_with(|| a.chunk_id.cmp(&b.chunk_id))
-            });
+    children.sort_unstable_by(|a, b| {
+        a.child_id
+            .cmp(&b.child_id)
+            .then_with(|| a.chunk_id.cmp(&b.chunk_id))
+    });
 
-            let name = if !data.is_empty() {
+    let (name_osk, raw_name) = if !data.is_empty() {
+        let Some(osk) = U16::<O>::read_from_prefix(data).map(|osk| osk.get()) else {
+            return Err(Error::truncated("name", end).in_chunk(id));
+        };
+        let (len, char_size) = match osk {
+            OSK_MAC | OSK_WINDOWS => {
                 let Some(string) = StringHeader::<O>::read_from_prefix(data) else {
-                    bail!("EOF in chunk name");
+                    return Err(Error::truncated("name", end).in_chunk(id));
                 };
                 data = &data[mem::size_of::<StringHeader<O>>()..];
-                match string.osk.get() {
-                    0x0303 => Cow::Borrowed(std::str::from_utf8(&data[..string.len as usize])?),
-                    0x0505 => {
-                        let mut value = Vec::with_capacity(string.len as usize);
-                        for c in data
-                            .chunks_exact(2)
-                            .take(string.len as usize)
-                            .map(|mut c| c.read_u16::<O>().unwrap())
-                        {
-                            value.push(c);
-                        }
-                        let Ok(value) = U16String::from_vec(value).to_string() else {
-                            bail!("Invalid utf-16");
-                        };
-                        Cow::Owned(value)
-                    }
-                    _ => {
-                        bail!("Unsupported string encoding");
-                    }
-                }
-            } else {
-                Cow::Borrowed("")
-            };
+                (string.len as usize, 1)
+            }
+            OSK_MAC_UNICODE | OSK_WINDOWS_UNICODE => {
+                let Some(string) = WideStringHeader::<O>::read_from_prefix(data) else {
+                    return Err(Error::truncated("name", end).in_chunk(id));
+                };
+                data = &data[mem::size_of::<WideStringHeader<O>>()..];
+                (string.len.get() as usize, 2)
+            }
+            _ => {
+                return Err(Error::unsupported(
+                    format!("name string kind {osk:#06x}"),
+                    end - data.len(),
+                )
+                .in_chunk(id))
+            }
+        };
+        match data.get(..len * char_size) {
+            Some(name) => (osk, name),
+            None => return Err(Error::truncated("name", end).in_chunk(id)),
+        }
+    } else {
+        (OSK_WINDOWS, &data[..0])
+    };
+    let (name, name_valid) = decode_name::<O>(name_osk, raw_name, WINDOWS_1252);
+
+    Ok(IndexEntry {
+        id,
+        offset: representation.offset,
+        flags: representation.flags,
+        length: representation.length,
+        name,
+        name_valid,
+        name_osk,
+        raw_name,
+        children,
+    })
+}
 
-            index.insert(
-                representation.id.swap(),
-                IndexEntry {
-                    offset: representation.offset.get(),
-                    flags: ChunkFlags::from_bits_retain(representation.grfcrp_cb.grfcrp()),
-                    length: representation.grfcrp_cb.length(),
-                    name,
-                    children,
-                },
+/// Decodes a name stored with string kind `osk`, returning false with it if it isn't valid in
+/// that encoding.
+fn decode_name<'a, O>(
+    osk: u16,
+    name: &'a [u8],
+    code_page: &'static Encoding,
+) -> (Cow<'a, str>, bool)
+where
+    O: ByteOrder,
+{
+    match osk {
+        OSK_WINDOWS => decode_single_byte(name, code_page),
+        OSK_MAC => decode_single_byte(name, MACINTOSH),
+        OSK_MAC_UNICODE | OSK_WINDOWS_UNICODE => {
+            let value = U16String::from_vec(
+                name.chunks_exact(2)
+                    .map(|mut c| c.read_u16::<O>().unwrap())
+                    .collect::<Vec<_>>(),
             );
+            match value.to_string() {
+                Ok(value) => (Cow::Owned(value), true),
+                Err(_) => (Cow::Owned(value.to_string_lossy()), false),
+            }
         }
+        // We don't know how to read this encoding, so keep the bytes we can show.
+        _ => (Cow::Owned(name.escape_ascii().to_string()), false),
+    }
+}
 
-        Ok(Group(index))
+fn decode_single_byte<'a>(name: &'a [u8], code_page: &'static Encoding) -> (Cow<'a, str>, bool) {
+    let (name, had_errors) = code_page.decode_without_bom_handling(name);
+    (name, !had_errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::LittleEndian;
+
+    use super::*;
+    use crate::{
+        chunky::testing::{chunk, file, id, link},
+        error::ErrorKind,
+    };
+
+    #[test]
+    fn chunk_ids() {
+        assert_eq!(
+            "TMPL:0x10".parse::<ChunkId>().unwrap(),
+            ChunkId {
+                tag: ChunkTag::from_bytes(*b"TMPL"),
+                number: U32::new(16),
+            }
+        );
+        assert_eq!(id("MTH:7").to_string(), "MTH :7");
+        assert!("TMPL".parse::<ChunkId>().is_err());
+        assert!("TEMPLATE:1".parse::<ChunkId>().is_err());
+    }
+
+    #[test]
+    fn owners() {
+        let output = file([
+            (
+                "TMPL:3",
+                chunk(
+                    ChunkFlags::LONER,
+                    "",
+                    vec![link("GLPI:7", 0), link("BMDL:8", 2)],
+                    b"",
+                ),
+            ),
+            ("GLPI:7", chunk(ChunkFlags::empty(), "", Vec::new(), b"")),
+            ("BMDL:8", chunk(ChunkFlags::empty(), "", Vec::new(), b"")),
+            ("BMDL:9", chunk(ChunkFlags::empty(), "", Vec::new(), b"")),
+        ]);
+        let file = ChunkyFile::load(&output).unwrap();
+        assert_eq!(file.owners(&id("GLPI:7"))[0].chunk_id, id("TMPL:3"));
+        assert_eq!(file.roots_of(&id("BMDL:8")), vec![id("TMPL:3")]);
+        assert!(!file.is_orphan(&id("TMPL:3")));
+        assert!(!file.is_orphan(&id("BMDL:8")));
+        assert!(file.is_orphan(&id("BMDL:9")));
+    }
+
+    #[test]
+    fn corrupt_index() {
+        let output = file([(
+            "TMPL:3",
+            chunk(
+                ChunkFlags::LONER,
+                "Willy",
+                vec![link("GLPI:7", 0)],
+                b"template",
+            ),
+        )]);
+
+        // Loading damaged files may fail, but it must not panic.
+        for i in 0..output.len() {
+            for value in [0x00, 0x7f, 0xff] {
+                let mut damaged = output.clone();
+                damaged[i] = value;
+                if let Ok(file) = ChunkyFile::load(&damaged) {
+                    for entry in file.index.values() {
+                        let _ = file.get_chunk(entry);
+                    }
+                }
+            }
+            let _ = ChunkyFile::load(&output[..i]);
+        }
+
+        let Err(e) = ChunkyFile::load(&output[..output.len() - 1]) else {
+            panic!("Loaded a truncated file");
+        };
+        assert!(matches!(e.kind, ErrorKind::Truncated("index")));
+        assert!(e.is_corrupt());
+    }
+
+    /// A small index entry for `TMPL:1` with no children, followed by `name`.
+    fn entry(name: &[u8]) -> Vec<u8> {
+        let mut data = b"LPMT\x01\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0".to_vec();
+        data.extend_from_slice(name);
+        data
+    }
+
+    #[test]
+    fn index_entry_names() {
+        let data = entry(b"\x03\x03\x04Caf\xe9\0");
+        let e = read_index_entry::<LittleEndian>(&data, 0, false).unwrap();
+        assert_eq!(e.id, id("TMPL:1"));
+        assert_eq!((e.name_osk, e.raw_name), (OSK_WINDOWS, &b"Caf\xe9"[..]));
+        assert_eq!(e.name, "Café");
+
+        // Kauai counts UTF-16 characters in a u16 rather than a byte.
+        let data = entry(b"\x05\x05\x02\0\x1a\x04\x3b\x04\0\0");
+        let e = read_index_entry::<LittleEndian>(&data, 0, false).unwrap();
+        assert_eq!(e.name_osk, OSK_WINDOWS_UNICODE);
+        assert_eq!(e.name, "Кл");
+
+        let data = entry(b"\x05\x05\x03\0\x1a\x04\x3b\x04");
+        let e = read_index_entry::<LittleEndian>(&data, 0, false).unwrap_err();
+        assert!(matches!(e.kind, ErrorKind::Truncated("name")));
+
+        let data = entry(b"\x07\x07\x02ab\0");
+        let e = read_index_entry::<LittleEndian>(&data, 0x10, false).unwrap_err();
+        assert!(matches!(e.kind, ErrorKind::Unsupported(_)));
+        assert_eq!(e.offset, 0x10 + 20);
+    }
+
+    #[test]
+    fn error_offsets() {
+        let mut output = file([(
+            "TMPL:1",
+            chunk(ChunkFlags::LONER, "", Vec::new(), b"template"),
+        )]);
+        let file = ChunkyFile::load(&output).unwrap();
+        let e = file.forest(&file.index[&id("TMPL:1")]).err().unwrap();
+        assert_eq!(e.offset, mem::size_of::<Prefix<LittleEndian>>());
+
+        // The byte order follows the magic number, creator and version.
+        output[12..14].copy_from_slice(&[2, 2]);
+        let e = ChunkyFile::load(&output).err().unwrap();
+        assert!(matches!(e.kind, ErrorKind::ByteOrder(0x0202)));
+        assert_eq!(e.offset, 12);
     }
 }
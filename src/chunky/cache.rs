@@ -0,0 +1,137 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::Deref,
+    sync::{Arc, Mutex},
+};
+
+use super::ChunkId;
+
+/// Unpacked chunk data.
+#[derive(Clone, Debug)]
+pub enum ChunkData<'a> {
+    /// Stored unpacked in the file.
+    Borrowed(&'a [u8]),
+    /// Unpacked for this caller alone.
+    Owned(Vec<u8>),
+    /// Unpacked once and shared through a [`ChunkCache`].
+    Shared(Arc<[u8]>),
+}
+
+impl Deref for ChunkData<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            ChunkData::Borrowed(data) => data,
+            ChunkData::Owned(data) => data,
+            ChunkData::Shared(data) => data,
+        }
+    }
+}
+
+impl AsRef<[u8]> for ChunkData<'_> {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+/// A least recently used cache of unpacked chunks, bounded by the total size of their data.
+///
+/// Lookups from several threads are fine. Two threads missing the same chunk at once will both
+/// unpack it, since holding the lock while decoding would serialize the whole pipeline.
+pub struct ChunkCache {
+    capacity: usize,
+    state: Mutex<CacheState>,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<ChunkId, (Arc<[u8]>, u64)>,
+    /// Chunks in the order they were last used.
+    recent: BTreeMap<u64, ChunkId>,
+    size: usize,
+    tick: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl ChunkCache {
+    /// Creates a cache holding up to `capacity` bytes of unpacked data.
+    pub fn new(capacity: usize) -> Self {
+        ChunkCache {
+            capacity,
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    pub fn get(&self, id: &ChunkId) -> Option<Arc<[u8]>> {
+        let mut state = self.state.lock().unwrap();
+        state.tick += 1;
+        let tick = state.tick;
+        let Some((data, last_used)) = state.entries.get_mut(id) else {
+            state.misses += 1;
+            return None;
+        };
+        let data = data.clone();
+        let previous = std::mem::replace(last_used, tick);
+        state.recent.remove(&previous);
+        state.recent.insert(tick, *id);
+        state.hits += 1;
+        Some(data)
+    }
+
+    /// Adds a chunk, evicting the least recently used ones to make room.
+    /// Chunks bigger than the whole cache aren't kept.
+    pub fn insert(&self, id: ChunkId, data: Arc<[u8]>) {
+        if data.len() > self.capacity {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.tick += 1;
+        let tick = state.tick;
+        state.size += data.len();
+        if let Some((old, last_used)) = state.entries.insert(id, (data, tick)) {
+            state.size -= old.len();
+            state.recent.remove(&last_used);
+        }
+        state.recent.insert(tick, id);
+
+        while state.size > self.capacity {
+            let Some((_, oldest)) = state.recent.pop_first() else {
+                break;
+            };
+            if let Some((old, _)) = state.entries.remove(&oldest) {
+                state.size -= old.len();
+            }
+        }
+    }
+
+    /// How many lookups found their chunk, and how many didn't.
+    pub fn stats(&self) -> (u64, u64) {
+        let state = self.state.lock().unwrap();
+        (state.hits, state.misses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunky::testing::id;
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let cache = ChunkCache::new(10);
+        cache.insert(id("TMAP:1"), Arc::from(&[1; 4][..]));
+        cache.insert(id("TMAP:2"), Arc::from(&[2; 4][..]));
+        assert!(cache.get(&id("TMAP:1")).is_some());
+        cache.insert(id("TMAP:3"), Arc::from(&[3; 4][..]));
+
+        assert_eq!(&cache.get(&id("TMAP:1")).unwrap()[..], &[1; 4]);
+        assert!(cache.get(&id("TMAP:2")).is_none());
+        assert_eq!(&cache.get(&id("TMAP:3")).unwrap()[..], &[3; 4]);
+
+        cache.insert(id("TMAP:4"), Arc::from(&[4; 11][..]));
+        assert!(cache.get(&id("TMAP:4")).is_none());
+        assert_eq!(cache.stats(), (3, 2));
+    }
+}
@@ -0,0 +1,52 @@
+//! Fixtures shared by tests that need a chunky file to work on.
+
+use std::borrow::Cow;
+
+use super::{ChildLink, ChunkFlags, ChunkId, ChunkTag, ChunkyWriter, NewChunk};
+
+/// Parses `TAG:number`.
+pub fn id(id: &str) -> ChunkId {
+    id.parse().unwrap()
+}
+
+pub fn link(chunk_id: &str, child_id: u32) -> ChildLink {
+    ChildLink {
+        chunk_id: id(chunk_id),
+        child_id,
+    }
+}
+
+pub fn chunk<'a>(
+    flags: ChunkFlags,
+    name: &'a str,
+    children: Vec<ChildLink>,
+    data: &'a [u8],
+) -> NewChunk<'a> {
+    NewChunk {
+        flags,
+        name: Cow::Borrowed(name),
+        unicode_name: false,
+        children,
+        data: Cow::Borrowed(data),
+    }
+}
+
+/// A writer for a file created by `CHMP`.
+pub fn writer<'a>() -> ChunkyWriter<'a> {
+    ChunkyWriter::new(ChunkTag::from_bytes(*b"CHMP"))
+}
+
+pub fn write(writer: &ChunkyWriter) -> Vec<u8> {
+    let mut output = Vec::new();
+    writer.write_to(&mut output).unwrap();
+    output
+}
+
+/// Writes `chunks` to a new file.
+pub fn file<'a>(chunks: impl IntoIterator<Item = (&'a str, NewChunk<'a>)>) -> Vec<u8> {
+    let mut writer = writer();
+    for (chunk_id, chunk) in chunks {
+        writer.insert(id(chunk_id), chunk);
+    }
+    write(&writer)
+}
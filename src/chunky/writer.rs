@@ -0,0 +1,361 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    io::Write,
+    mem,
+};
+
+use anyhow::{ensure, Context, Result};
+use byteorder::{LittleEndian, WriteBytesExt};
+use encoding_rs::{Encoding, WINDOWS_1252};
+
+use super::{
+    ChildLink, ChunkFlags, ChunkId, ChunkRepresentationLarge, ChunkRepresentationSmall, ChunkTag,
+    GroupOnFile, Loc, Prefix, BACKWARDS_VERSION, CURRENT_VERSION, MAGIC, OSK_WINDOWS,
+    OSK_WINDOWS_UNICODE,
+};
+use crate::order::BYTE_ORDER_NATIVE;
+
+/// A chunk waiting to be written by a [`ChunkyWriter`].
+#[derive(Debug)]
+pub struct NewChunk<'a> {
+    pub flags: ChunkFlags,
+    pub name: Cow<'a, str>,
+    /// Writes the name as UTF-16 even if the writer's code page can hold it.
+    pub unicode_name: bool,
+    pub children: Vec<ChildLink>,
+    /// The chunk data exactly as it should appear on disk.
+    /// If `flags` contains [`ChunkFlags::PACKED`] this must already be compressed.
+    pub data: Cow<'a, [u8]>,
+}
+
+/// Builds a little endian CHN2 file from a set of chunks.
+pub struct ChunkyWriter<'a> {
+    creator: ChunkTag,
+    chunks: BTreeMap<ChunkId, NewChunk<'a>>,
+    code_page: &'static Encoding,
+}
+
+impl<'a> ChunkyWriter<'a> {
+    pub fn new(creator: ChunkTag) -> Self {
+        ChunkyWriter {
+            creator,
+            chunks: BTreeMap::new(),
+            code_page: WINDOWS_1252,
+        }
+    }
+
+    /// Writes names in `code_page` instead of Windows-1252. Names it can't represent are
+    /// written as UTF-16.
+    pub fn with_code_page(mut self, code_page: &'static Encoding) -> Self {
+        self.code_page = code_page;
+        self
+    }
+
+    pub fn insert(&mut self, id: ChunkId, chunk: NewChunk<'a>) -> Option<NewChunk<'a>> {
+        self.chunks.insert(id, chunk)
+    }
+
+    /// Drops the chunks for which `keep` returns false. Links to them are left in place.
+    pub fn retain<F>(&mut self, mut keep: F)
+    where
+        F: FnMut(&ChunkId) -> bool,
+    {
+        self.chunks.retain(|id, _| keep(id));
+    }
+
+    pub fn write_to<W>(&self, mut output: W) -> Result<()>
+    where
+        W: Write,
+    {
+        let mut owner_counts: HashMap<ChunkId, u32> = HashMap::new();
+        for chunk in self.chunks.values() {
+            for child in &chunk.children {
+                *owner_counts.entry(child.chunk_id).or_default() += 1;
+            }
+        }
+
+        // The index uses one representation for every chunk, so a single chunk that doesn't fit
+        // the small representation forces the large one for the whole file.
+        let large = self
+            .chunks
+            .values()
+            .any(|c| c.data.len() >= 1 << 24 || c.children.len() > u16::MAX as usize)
+            || owner_counts.values().any(|c| *c > u16::MAX as u32);
+
+        // Chunk data goes first, followed by the index.
+        let mut offset = mem::size_of::<Prefix<LittleEndian>>();
+        let mut entries = Vec::new();
+        let mut locs = Vec::with_capacity(self.chunks.len());
+        for (id, chunk) in &self.chunks {
+            let start = entries.len();
+            write_entry(
+                &mut entries,
+                id,
+                chunk,
+                self.code_page,
+                offset,
+                owner_counts.get(id).copied().unwrap_or(0),
+                large,
+            )
+            .with_context(|| format!("Writing index entry for {id:?}"))?;
+            locs.push((start, entries.len() - start));
+            offset += chunk.data.len();
+        }
+
+        let index_offset = offset;
+        let index_len = mem::size_of::<GroupOnFile<LittleEndian>>()
+            + entries.len()
+            + locs.len() * mem::size_of::<Loc<LittleEndian>>();
+        let eof = index_offset + index_len;
+        ensure!(u32::try_from(eof).is_ok(), "File too large");
+
+        // Prefix
+        output.write_all(MAGIC)?;
+        output.write_u32::<LittleEndian>(self.creator.0.get())?;
+        output.write_u16::<LittleEndian>(CURRENT_VERSION)?;
+        output.write_u16::<LittleEndian>(BACKWARDS_VERSION)?;
+        output.write_u16::<LittleEndian>(BYTE_ORDER_NATIVE)?;
+        output.write_u16::<LittleEndian>(OSK_WINDOWS)?;
+        output.write_u32::<LittleEndian>(eof as u32)?;
+        output.write_u32::<LittleEndian>(index_offset as u32)?;
+        output.write_u32::<LittleEndian>(index_len as u32)?;
+        // There is no free space, so the free map is empty.
+        output.write_u32::<LittleEndian>(eof as u32)?;
+        output.write_u32::<LittleEndian>(0)?;
+        output.write_all(&[0; 23 * 4])?;
+
+        for chunk in self.chunks.values() {
+            output.write_all(&chunk.data)?;
+        }
+
+        // Index
+        output.write_u16::<LittleEndian>(BYTE_ORDER_NATIVE)?;
+        output.write_u16::<LittleEndian>(OSK_WINDOWS)?;
+        output.write_u32::<LittleEndian>(locs.len() as u32)?;
+        output.write_u32::<LittleEndian>(entries.len() as u32)?;
+        output.write_u32::<LittleEndian>(0)?;
+        output.write_u32::<LittleEndian>(if large {
+            mem::size_of::<ChunkRepresentationLarge<LittleEndian>>() as u32
+        } else {
+            mem::size_of::<ChunkRepresentationSmall<LittleEndian>>() as u32
+        })?;
+        output.write_all(&entries)?;
+        for (offset, length) in locs {
+            output.write_u32::<LittleEndian>(offset as u32)?;
+            output.write_u32::<LittleEndian>(length as u32)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_entry(
+    output: &mut Vec<u8>,
+    id: &ChunkId,
+    chunk: &NewChunk,
+    code_page: &'static Encoding,
+    offset: usize,
+    owner_count: u32,
+    large: bool,
+) -> Result<()> {
+    let length = u32::try_from(chunk.data.len()).context("Chunk data too large")?;
+
+    let start = output.len();
+    output.write_u32::<LittleEndian>(id.tag.0.get())?;
+    output.write_u32::<LittleEndian>(id.number.get())?;
+    output.write_u32::<LittleEndian>(offset as u32)?;
+    if large {
+        output.write_u32::<LittleEndian>(length)?;
+        output.write_u32::<LittleEndian>(chunk.children.len() as u32)?;
+        output.write_u32::<LittleEndian>(owner_count)?;
+        output.write_u32::<LittleEndian>(0)?;
+        output.write_u32::<LittleEndian>(chunk.flags.bits() as u32)?;
+        debug_assert_eq!(
+            output.len() - start,
+            mem::size_of::<ChunkRepresentationLarge<LittleEndian>>(),
+        );
+    } else {
+        output.write_u32::<LittleEndian>(length << 8 | chunk.flags.bits() as u32)?;
+        output.write_u16::<LittleEndian>(chunk.children.len() as u16)?;
+        output.write_u16::<LittleEndian>(owner_count as u16)?;
+        debug_assert_eq!(
+            output.len() - start,
+            mem::size_of::<ChunkRepresentationSmall<LittleEndian>>(),
+        );
+    }
+
+    let mut children: Vec<_> = chunk.children.iter().collect();
+    children.sort_unstable_by(|a, b| {
+        a.child_id
+            .cmp(&b.child_id)
+            .then_with(|| a.chunk_id.cmp(&b.chunk_id))
+    });
+    for child in children {
+        output.write_u32::<LittleEndian>(child.chunk_id.tag.0.get())?;
+        output.write_u32::<LittleEndian>(child.chunk_id.number.get())?;
+        output.write_u32::<LittleEndian>(child.child_id)?;
+    }
+
+    if !chunk.name.is_empty() {
+        let (name, _, unmappable) = code_page.encode(&chunk.name);
+        if !unmappable && !chunk.unicode_name {
+            ensure!(name.len() <= u8::MAX as usize, "Name too long");
+            output.write_u16::<LittleEndian>(OSK_WINDOWS)?;
+            output.write_u8(name.len() as u8)?;
+            output.extend_from_slice(&name);
+            output.write_u8(0)?;
+        } else {
+            // Unlike single byte names, Kauai counts UTF-16 characters in a u16.
+            let name: Vec<_> = chunk.name.encode_utf16().collect();
+            ensure!(name.len() <= u16::MAX as usize, "Name too long");
+            output.write_u16::<LittleEndian>(OSK_WINDOWS_UNICODE)?;
+            output.write_u16::<LittleEndian>(name.len() as u16)?;
+            for c in name {
+                output.write_u16::<LittleEndian>(c)?;
+            }
+            output.write_u16::<LittleEndian>(0)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunky::{
+            testing::{chunk, file, id, link, write, writer},
+            ChunkyFile,
+        },
+        order::Loader,
+    };
+
+    #[test]
+    fn round_trip() {
+        let mut writer = writer();
+        writer.insert(
+            id("TMPL:3"),
+            chunk(
+                ChunkFlags::LONER,
+                "Willy",
+                vec![link("GLPI:7", 0), link("BMDL:8", 2)],
+                b"template",
+            ),
+        );
+        writer.insert(
+            id("GLPI:7"),
+            chunk(ChunkFlags::empty(), "", Vec::new(), b"armature"),
+        );
+        writer.insert(
+            id("BMDL:8"),
+            chunk(ChunkFlags::empty(), "", Vec::new(), b""),
+        );
+        let output = write(&writer);
+
+        let file = ChunkyFile::load(&output).unwrap();
+        assert_eq!(file.creator, "CHMP");
+        assert_eq!(file.index.len(), 3);
+
+        let template = &file.index[&id("TMPL:3")];
+        assert_eq!(template.flags, ChunkFlags::LONER);
+        assert_eq!(template.name, "Willy");
+        assert_eq!(&file.get_chunk(template).unwrap()[..], b"template");
+        assert_eq!(template.get_child(0, "GLPI"), Some(&id("GLPI:7")));
+        assert_eq!(template.get_child(2, "BMDL"), Some(&id("BMDL:8")));
+
+        let armature = &file.index[&id("GLPI:7")];
+        assert_eq!(armature.name, "");
+        assert_eq!(&file.get_chunk(armature).unwrap()[..], b"armature");
+        assert!(file
+            .get_chunk(&file.index[&id("BMDL:8")])
+            .unwrap()
+            .is_empty());
+
+        let mut rewritten = Vec::new();
+        file.to_writer().unwrap().write_to(&mut rewritten).unwrap();
+        assert_eq!(output, rewritten);
+    }
+
+    #[test]
+    fn large_representation() {
+        let output = file([
+            (
+                "GGCL:1",
+                chunk(
+                    ChunkFlags::LONER,
+                    "cells",
+                    (0..=u16::MAX as u32 + 1)
+                        .map(|child_id| link("BMDL:2", child_id))
+                        .collect(),
+                    b"cells",
+                ),
+            ),
+            (
+                "BMDL:2",
+                chunk(ChunkFlags::PACKED, "", Vec::new(), b"model"),
+            ),
+        ]);
+
+        let file = ChunkyFile::load(&output).unwrap();
+        let cells = &file.index[&id("GGCL:1")];
+        assert_eq!(cells.children.len(), u16::MAX as usize + 2);
+        assert_eq!(cells.flags, ChunkFlags::LONER);
+        assert_eq!(cells.name, "cells");
+        assert_eq!(cells.get_child(65536, "BMDL"), Some(&id("BMDL:2")));
+        let model = &file.index[&id("BMDL:2")];
+        assert_eq!(model.flags, ChunkFlags::PACKED);
+        assert_eq!(model.length, 5);
+        assert_eq!(file.owners(&id("BMDL:2")).len(), u16::MAX as usize + 2);
+    }
+
+    #[test]
+    fn code_pages() {
+        let write = |mut writer: ChunkyWriter| {
+            writer.insert(
+                id("TMPL:1"),
+                chunk(ChunkFlags::LONER, "Café", Vec::new(), b""),
+            );
+            writer.insert(
+                id("TMPL:2"),
+                chunk(ChunkFlags::LONER, "Ключ", Vec::new(), b""),
+            );
+            write(&writer)
+        };
+
+        // Windows-1252 can't hold Cyrillic, so that name falls back to UTF-16.
+        let output = write(writer());
+        let file = ChunkyFile::load(&output).unwrap();
+        let cafe = &file.index[&id("TMPL:1")];
+        assert_eq!(
+            (cafe.name_osk, cafe.raw_name),
+            (OSK_WINDOWS, &b"Caf\xe9"[..])
+        );
+        assert_eq!(cafe.name, "Café");
+        assert_eq!(file.index[&id("TMPL:2")].name_osk, OSK_WINDOWS_UNICODE);
+        assert_eq!(file.index[&id("TMPL:2")].name, "Ключ");
+
+        let output = write(writer().with_code_page(encoding_rs::WINDOWS_1251));
+        let file = ChunkyFile::load(&output).unwrap();
+        assert_eq!(file.index[&id("TMPL:2")].name, "Êëþ÷");
+        let file = file.with_code_page(encoding_rs::WINDOWS_1251);
+        assert_eq!(file.index[&id("TMPL:2")].name, "Ключ");
+        assert_eq!(file.index[&id("TMPL:1")].name_osk, OSK_WINDOWS_UNICODE);
+    }
+
+    #[test]
+    fn unicode_name() {
+        let mut writer = writer();
+        let mut template = chunk(ChunkFlags::LONER, "Кл", Vec::new(), b"");
+        template.unicode_name = true;
+        writer.insert(id("TMPL:1"), template);
+        let output = write(&writer);
+
+        // The index entry, with the name as Kauai writes a UTF-16 STN, then its location.
+        let mut expected = b"LPMT\x01\0\0\0\x80\0\0\0\x02\0\0\0\0\0\0\0".to_vec();
+        expected.extend_from_slice(b"\x05\x05\x02\0\x1a\x04\x3b\x04\0\0");
+        expected.extend_from_slice(b"\0\0\0\0\x1e\0\0\0");
+        assert!(output.ends_with(&expected));
+    }
+}
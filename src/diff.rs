@@ -0,0 +1,189 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+};
+
+use crate::{
+    chunky::{ChunkId, ChunkyFile, IndexEntry},
+    registry::TypedChunk,
+};
+
+/// The most field differences reported for one chunk, since a reworked model can differ in
+/// thousands of vertices.
+const MAX_FIELD_CHANGES: usize = 20;
+
+pub enum Change {
+    Added(ChunkId),
+    Removed(ChunkId),
+    Changed { id: ChunkId, details: Vec<String> },
+}
+
+impl Change {
+    pub fn id(&self) -> &ChunkId {
+        match self {
+            Change::Added(id) | Change::Removed(id) | Change::Changed { id, .. } => id,
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added(id) => write!(f, "+ {id}"),
+            Change::Removed(id) => write!(f, "- {id}"),
+            Change::Changed { id, details } => {
+                write!(f, "~ {id}")?;
+                for detail in details {
+                    write!(f, "\n    {detail}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Compares two files chunk by chunk. Chunk data is compared unpacked, so repacking a chunk
+/// doesn't count as a change.
+pub fn diff(old: &ChunkyFile, new: &ChunkyFile) -> Vec<Change> {
+    let ids: BTreeSet<_> = old.index.keys().chain(new.index.keys()).collect();
+    ids.into_iter()
+        .filter_map(|id| match (old.index.get(id), new.index.get(id)) {
+            (Some(_), None) => Some(Change::Removed(*id)),
+            (None, Some(_)) => Some(Change::Added(*id)),
+            (Some(a), Some(b)) => {
+                let details = diff_entries(old, a, new, b);
+                (!details.is_empty()).then_some(Change::Changed { id: *id, details })
+            }
+            (None, None) => unreachable!(),
+        })
+        .collect()
+}
+
+fn diff_entries(
+    old_file: &ChunkyFile,
+    old: &IndexEntry,
+    new_file: &ChunkyFile,
+    new: &IndexEntry,
+) -> Vec<String> {
+    let mut details = Vec::new();
+    if old.name != new.name {
+        details.push(format!("name: {:?} -> {:?}", old.name, new.name));
+    }
+    if old.flags != new.flags {
+        details.push(format!("flags: {} -> {}", old.flags, new.flags));
+    }
+
+    let links = |entry: &IndexEntry| -> BTreeSet<_> {
+        entry
+            .children
+            .iter()
+            .map(|c| (c.child_id, c.chunk_id))
+            .collect()
+    };
+    let (old_links, new_links) = (links(old), links(new));
+    for (child_id, chunk_id) in old_links.difference(&new_links) {
+        details.push(format!("- child {child_id} {chunk_id}"));
+    }
+    for (child_id, chunk_id) in new_links.difference(&old_links) {
+        details.push(format!("+ child {child_id} {chunk_id}"));
+    }
+
+    let (old_data, new_data) = match (old_file.get_chunk(old), new_file.get_chunk(new)) {
+        (Ok(a), Ok(b)) => (a, b),
+        (Err(e), _) | (_, Err(e)) => {
+            details.push(format!("data can't be compared: {e}"));
+            return details;
+        }
+    };
+    if old_data[..] == new_data[..] {
+        return details;
+    }
+    let (old_len, new_len) = (old_data.len(), new_data.len());
+    let typed = TypedChunk::decode(old.id.tag, old_data)
+        .and_then(|a| Ok((a, TypedChunk::decode(new.id.tag, new_data)?)));
+    match typed {
+        Ok((a, b)) if !matches!(a, TypedChunk::Raw(_)) => diff_fields(&a, &b, &mut details),
+        _ => details.push(format!("data: {old_len} bytes -> {new_len} bytes")),
+    }
+    details
+}
+
+fn diff_fields(old: &TypedChunk, new: &TypedChunk, details: &mut Vec<String>) {
+    let old = old.fields();
+    let new = new.fields();
+    let old_values: HashMap<_, _> = old.iter().map(|(p, v)| (p, v)).collect();
+    let new_values: HashMap<_, _> = new.iter().map(|(p, v)| (p, v)).collect();
+    let mut changes = Vec::new();
+    for (path, a) in &old {
+        match new_values.get(path) {
+            Some(b) if a != *b => changes.push(format!("{path}: {a} -> {b}")),
+            Some(_) => {}
+            None => changes.push(format!("- {path}: {a}")),
+        }
+    }
+    for (path, b) in &new {
+        if !old_values.contains_key(path) {
+            changes.push(format!("+ {path}: {b}"));
+        }
+    }
+    let total = changes.len();
+    details.extend(changes.into_iter().take(MAX_FIELD_CHANGES));
+    if total > MAX_FIELD_CHANGES {
+        details.push(format!("... and {} more", total - MAX_FIELD_CHANGES));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunky::{
+            testing::{chunk, file, link},
+            ChunkFlags,
+        },
+        kauai,
+        order::Loader,
+    };
+
+    /// A little endian GLBS list of body part groups.
+    fn body_part_sets(groups: &[u16]) -> Vec<u8> {
+        let mut data = vec![0x01, 0x00, 0x03, 0x03, 0x02, 0x00, 0x00, 0x00];
+        data.extend_from_slice(&(groups.len() as u32).to_le_bytes());
+        for group in groups {
+            data.extend_from_slice(&group.to_le_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn reports_changes() {
+        let old_sets = body_part_sets(&[0, 1, 1]);
+        let old = file([
+            (
+                "GLBS:1",
+                chunk(ChunkFlags::empty(), "", Vec::new(), &old_sets),
+            ),
+            ("TMPL:2", chunk(ChunkFlags::LONER, "Old", Vec::new(), b"")),
+        ]);
+        let new_sets = kauai::encode(b"KCDC", &body_part_sets(&[0, 2, 1, 1])).unwrap();
+        let new = file([
+            (
+                "GLBS:1",
+                chunk(ChunkFlags::PACKED, "", vec![link("TMPL:3", 0)], &new_sets),
+            ),
+            ("TMPL:3", chunk(ChunkFlags::LONER, "New", Vec::new(), b"")),
+        ]);
+
+        let old = ChunkyFile::load(&old).unwrap();
+        let new = ChunkyFile::load(&new).unwrap();
+        let changes: Vec<_> = diff(&old, &new).iter().map(|c| c.to_string()).collect();
+        assert_eq!(
+            changes,
+            vec![
+                "~ GLBS:1\n    flags: none -> PACKED\n    + child 0 TMPL:3\n    groups[1]: 1 -> 2\n    + groups[3]: 1",
+                "- TMPL:2",
+                "+ TMPL:3",
+            ],
+        );
+    }
+}
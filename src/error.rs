@@ -0,0 +1,106 @@
+use std::fmt;
+
+use thiserror::Error;
+
+use crate::chunky::{ChunkId, ChunkTag};
+
+/// An error from parsing a chunky file or the chunks inside it.
+#[derive(Debug, Error)]
+pub struct Error {
+    pub kind: ErrorKind,
+    /// The chunk being read, if the problem is with a particular chunk.
+    pub chunk: Option<ChunkId>,
+    /// Where the problem was found. This counts from the start of the chunk's unpacked data for
+    /// problems inside a chunk, and from the start of the file for problems with the file
+    /// structure.
+    pub offset: usize,
+}
+
+#[derive(Debug, Error)]
+pub enum ErrorKind {
+    /// The data ended before the named structure did.
+    #[error("Unexpected end of data in {0}")]
+    Truncated(&'static str),
+    #[error("Unexpected byte order {0:#06x}")]
+    ByteOrder(u16),
+    #[error("Unsupported codec {}", .0.escape_ascii())]
+    UnsupportedCodec([u8; 4]),
+    #[error("Unsupported pixel type {0}")]
+    UnsupportedPixelType(u8),
+    #[error("Missing child {child_id} with tag {tag}")]
+    MissingChild { tag: ChunkTag, child_id: u32 },
+    /// The data is malformed in some other way.
+    #[error("{0}")]
+    Invalid(String),
+    /// The data looks valid but uses a feature we can't read yet.
+    #[error("Unsupported {0}")]
+    Unsupported(String),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    pub fn new(kind: ErrorKind, offset: usize) -> Self {
+        Error {
+            kind,
+            chunk: None,
+            offset,
+        }
+    }
+
+    pub fn truncated(what: &'static str, offset: usize) -> Self {
+        Error::new(ErrorKind::Truncated(what), offset)
+    }
+
+    pub fn invalid(message: impl Into<String>, offset: usize) -> Self {
+        Error::new(ErrorKind::Invalid(message.into()), offset)
+    }
+
+    pub fn unsupported(feature: impl Into<String>, offset: usize) -> Self {
+        Error::new(ErrorKind::Unsupported(feature.into()), offset)
+    }
+
+    pub fn missing_child(parent: ChunkId, tag: &str, child_id: u32) -> Self {
+        let tag = tag
+            .parse()
+            .unwrap_or_else(|_| ChunkTag::from_bytes(*b"????"));
+        Error::new(ErrorKind::MissingChild { tag, child_id }, 0).in_chunk(parent)
+    }
+
+    /// Attributes the error to `chunk` unless it already names one.
+    pub fn in_chunk(mut self, chunk: ChunkId) -> Self {
+        self.chunk.get_or_insert(chunk);
+        self
+    }
+
+    /// Moves the error's offset to account for data before the part that was being parsed.
+    pub fn offset_by(mut self, start: usize) -> Self {
+        self.offset += start;
+        self
+    }
+
+    /// Whether the data might be fine, but we don't know how to read it yet.
+    pub fn is_unsupported(&self) -> bool {
+        matches!(
+            self.kind,
+            ErrorKind::UnsupportedCodec(_)
+                | ErrorKind::UnsupportedPixelType(_)
+                | ErrorKind::Unsupported(_)
+        )
+    }
+
+    /// Whether the data is damaged or inconsistent.
+    pub fn is_corrupt(&self) -> bool {
+        !self.is_unsupported()
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {:#x}", self.kind, self.offset)?;
+        if let Some(chunk) = &self.chunk {
+            write!(f, " in {chunk}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,416 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, mem,
+    ops::Range,
+};
+
+use byteorder::LittleEndian;
+
+use crate::{
+    chunky::{ChunkFlags, ChunkId, ChunkTag, ChunkyFile, Prefix},
+    error::Error,
+    kauai,
+};
+
+/// Something wrong with a chunky file that doesn't stop it from loading.
+pub enum Problem {
+    /// A child link to a chunk that isn't in the index.
+    DanglingChild {
+        parent: ChunkId,
+        child: ChunkId,
+        child_id: u32,
+    },
+    /// Chunks that are, through their children, their own descendants.
+    Cycle(Vec<ChunkId>),
+    PastEof {
+        id: ChunkId,
+        range: Range<usize>,
+    },
+    /// Two chunks, or a chunk and the file's own structures, sharing bytes.
+    Overlap {
+        first: String,
+        second: String,
+        range: Range<usize>,
+    },
+    /// Flagged as PACKED, but without a Kauai codec header.
+    MissingCodec(ChunkId),
+    /// Has a Kauai codec header that decodes, but isn't flagged as PACKED.
+    UnflaggedPacked {
+        id: ChunkId,
+        codec: [u8; 4],
+    },
+    Undecodable {
+        id: ChunkId,
+        error: Error,
+    },
+    /// Several children share a child ID and tag, so lookups can return any of them.
+    DuplicateChild {
+        parent: ChunkId,
+        child_id: u32,
+        tag: ChunkTag,
+    },
+    InvalidName(ChunkId),
+    /// Flagged as FOREST, but doesn't hold a chunky file we can read.
+    BadForest {
+        id: ChunkId,
+        error: Error,
+    },
+    /// A problem with the file held by a forest chunk.
+    InForest {
+        forest: ChunkId,
+        problem: Box<Problem>,
+    },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::DanglingChild {
+                parent,
+                child,
+                child_id,
+            } => write!(
+                f,
+                "{parent} child {child_id} is {child}, which doesn't exist"
+            ),
+            Problem::Cycle(ids) => {
+                write!(f, "cycle: ")?;
+                for id in ids {
+                    write!(f, "{id} -> ")?;
+                }
+                write!(f, "{}", ids[0])
+            }
+            Problem::PastEof { id, range } => {
+                write!(f, "{id} data {range:#x?} runs past the end of the file")
+            }
+            Problem::Overlap {
+                first,
+                second,
+                range,
+            } => write!(f, "{first} and {second} overlap at {range:#x?}"),
+            Problem::MissingCodec(id) => write!(f, "{id} is flagged PACKED but has no codec"),
+            Problem::UnflaggedPacked { id, codec } => write!(
+                f,
+                "{id} is {} data but isn't flagged PACKED",
+                codec.escape_ascii(),
+            ),
+            Problem::Undecodable { id, error } => write!(f, "{id} can't be unpacked: {error}"),
+            Problem::DuplicateChild {
+                parent,
+                child_id,
+                tag,
+            } => write!(
+                f,
+                "{parent} has several {tag} children with child ID {child_id}"
+            ),
+            Problem::InvalidName(id) => write!(f, "{id} has an invalid name"),
+            Problem::BadForest { id, error } => {
+                write!(f, "{id} can't be read as a forest: {error}")
+            }
+            Problem::InForest { forest, problem } => write!(f, "in forest {forest}: {problem}"),
+        }
+    }
+}
+
+/// Checks everything we know how to check, reporting every problem found, including those in
+/// the files held by forest chunks.
+pub fn check(file: &ChunkyFile) -> Vec<Problem> {
+    let ids: Vec<_> = file.index.keys().copied().collect();
+    check_chunks(file, &ids)
+}
+
+/// Like [`check`], but only reports problems with the chunks in `ids`: their links, their data,
+/// cycles through what they reach, and overlaps between their data and anything else.
+pub fn check_chunks(file: &ChunkyFile, ids: &[ChunkId]) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    let mut ids = ids.to_vec();
+    ids.sort_unstable();
+    let ids = &ids[..];
+
+    for id in ids {
+        let entry = &file.index[id];
+        for child in &entry.children {
+            if !file.index.contains_key(&child.chunk_id) {
+                problems.push(Problem::DanglingChild {
+                    parent: *id,
+                    child: child.chunk_id,
+                    child_id: child.child_id,
+                });
+            }
+        }
+        // Children are sorted by child ID, then chunk ID, so a clash is always a neighbour.
+        for pair in entry.children.windows(2) {
+            if pair[0].child_id == pair[1].child_id && pair[0].chunk_id.tag == pair[1].chunk_id.tag
+            {
+                problems.push(Problem::DuplicateChild {
+                    parent: *id,
+                    child_id: pair[0].child_id,
+                    tag: pair[0].chunk_id.tag,
+                });
+            }
+        }
+        if !entry.name_valid {
+            problems.push(Problem::InvalidName(*id));
+        }
+    }
+
+    problems.extend(find_cycles(file, ids).into_iter().map(Problem::Cycle));
+    check_ranges(file, ids, &mut problems);
+
+    for id in ids {
+        let entry = &file.index[id];
+        let Ok(data) = file.raw_chunk(entry) else {
+            continue;
+        };
+        let codec = data
+            .get(..4)
+            .filter(|c| *c == b"KCDC" || *c == b"KCD2")
+            .map(|c| <[u8; 4]>::try_from(c).unwrap());
+        match (entry.flags.contains(ChunkFlags::PACKED), codec) {
+            (true, None) => problems.push(Problem::MissingCodec(*id)),
+            (true, Some(_)) => {
+                if let Err(error) = file.get_chunk(entry) {
+                    problems.push(Problem::Undecodable { id: *id, error });
+                }
+            }
+            (false, Some(codec)) => {
+                if kauai::decode(data).is_ok() {
+                    problems.push(Problem::UnflaggedPacked { id: *id, codec });
+                }
+            }
+            (false, None) => {}
+        }
+    }
+
+    for id in ids {
+        let entry = &file.index[id];
+        if !entry.flags.contains(ChunkFlags::FOREST) {
+            continue;
+        }
+        let nested = file
+            .forest(entry)
+            .and_then(|forest| Ok(check(&forest.file()?)));
+        match nested {
+            Ok(nested) => problems.extend(nested.into_iter().map(|problem| Problem::InForest {
+                forest: *id,
+                problem: Box::new(problem),
+            })),
+            Err(error) => problems.push(Problem::BadForest { id: *id, error }),
+        }
+    }
+
+    problems
+}
+
+fn check_ranges(file: &ChunkyFile, ids: &[ChunkId], problems: &mut Vec<Problem>) {
+    let checked: HashSet<_> = ids.iter().copied().collect();
+    let mut ranges = vec![
+        (
+            "the file header".to_owned(),
+            0..mem::size_of::<Prefix<LittleEndian>>(),
+        ),
+        ("the index".to_owned(), file.index_range.clone()),
+        ("the free map".to_owned(), file.free_map_range.clone()),
+    ];
+    let mut entries: Vec<_> = file.index.values().collect();
+    entries.sort_unstable_by_key(|e| e.id);
+    let mut names = HashSet::new();
+    for entry in entries {
+        let range = entry.offset as usize..entry.offset as usize + entry.length as usize;
+        if checked.contains(&entry.id) {
+            if range.end > file.data.len() {
+                problems.push(Problem::PastEof {
+                    id: entry.id,
+                    range: range.clone(),
+                });
+            }
+            names.insert(entry.id.to_string());
+        }
+        ranges.push((entry.id.to_string(), range));
+    }
+
+    // Overlaps between the file's own structures are only reported when checking every chunk.
+    let whole_file = checked.len() == file.index.len();
+    problems.extend(find_overlaps(ranges).into_iter().filter(|overlap| {
+        whole_file
+            || matches!(overlap, Problem::Overlap { first, second, .. }
+                if names.contains(first) || names.contains(second))
+    }));
+}
+
+/// Reports every pair of named ranges that share bytes.
+fn find_overlaps(mut ranges: Vec<(String, Range<usize>)>) -> Vec<Problem> {
+    ranges.retain(|(_, r)| !r.is_empty());
+    ranges.sort_by_key(|(_, r)| (r.start, r.end));
+    // Sweep through the ranges in order of their start, comparing each with the earlier ranges
+    // that haven't ended yet.
+    let mut overlaps = Vec::new();
+    let mut open: Vec<&(String, Range<usize>)> = Vec::new();
+    for current in &ranges {
+        open.retain(|previous| previous.1.end > current.1.start);
+        for previous in &open {
+            overlaps.push(Problem::Overlap {
+                first: previous.0.clone(),
+                second: current.0.clone(),
+                range: current.1.start..current.1.end.min(previous.1.end),
+            });
+        }
+        open.push(current);
+    }
+    overlaps
+}
+
+/// Finds cycles in the child graph, reporting each one once.
+fn find_cycles(file: &ChunkyFile, ids: &[ChunkId]) -> Vec<Vec<ChunkId>> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum State {
+        InProgress,
+        Done,
+    }
+
+    let mut state: HashMap<ChunkId, State> = HashMap::new();
+    let mut cycles = Vec::new();
+    let mut reported = HashSet::new();
+    for root in ids {
+        if state.contains_key(root) {
+            continue;
+        }
+        // Depth first, keeping the path so a cycle can be reported in full.
+        let mut path: Vec<(ChunkId, usize)> = vec![(*root, 0)];
+        state.insert(*root, State::InProgress);
+        while let Some((id, next)) = path.last_mut() {
+            let children = &file.index[id].children;
+            let Some(child) = children.get(*next) else {
+                state.insert(*id, State::Done);
+                path.pop();
+                continue;
+            };
+            *next += 1;
+            let child = child.chunk_id;
+            if !file.index.contains_key(&child) {
+                continue;
+            }
+            match state.get(&child) {
+                None => {
+                    state.insert(child, State::InProgress);
+                    path.push((child, 0));
+                }
+                Some(State::InProgress) => {
+                    let start = path.iter().position(|(id, _)| *id == child).unwrap();
+                    let mut cycle: Vec<_> = path[start..].iter().map(|(id, _)| *id).collect();
+                    // Start from the smallest ID so the same cycle is reported the same way.
+                    let smallest = (0..cycle.len()).min_by_key(|i| cycle[*i]).unwrap();
+                    cycle.rotate_left(smallest);
+                    if reported.insert(cycle.clone()) {
+                        cycles.push(cycle);
+                    }
+                }
+                Some(State::Done) => {}
+            }
+        }
+    }
+    cycles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunky::testing::{chunk, id, link, write, writer},
+        order::Loader,
+    };
+
+    #[test]
+    fn nested_overlaps() {
+        let overlaps: Vec<_> = find_overlaps(vec![
+            ("TMPL:1".to_owned(), 0..100),
+            ("ACTN:2".to_owned(), 10..20),
+            ("GGCL:3".to_owned(), 15..30),
+            ("GGCL:4".to_owned(), 30..40),
+            ("GLXF:5".to_owned(), 100..110),
+        ])
+        .iter()
+        .map(|p| p.to_string())
+        .collect();
+        assert_eq!(
+            overlaps,
+            vec![
+                "TMPL:1 and ACTN:2 overlap at 0xa..0x14",
+                "TMPL:1 and GGCL:3 overlap at 0xf..0x1e",
+                "ACTN:2 and GGCL:3 overlap at 0xf..0x14",
+                "TMPL:1 and GGCL:4 overlap at 0x1e..0x28",
+            ],
+        );
+    }
+
+    #[test]
+    fn finds_problems() {
+        let cells = kauai::encode(b"KCDC", b"cells").unwrap();
+        let mut nested = writer();
+        nested.insert(
+            id("TMPL:1"),
+            chunk(ChunkFlags::LONER, "", vec![link("ACTN:2", 0)], b""),
+        );
+        let forest = write(&nested);
+
+        let mut writer = writer();
+        writer.insert(
+            id("ACTN:1"),
+            chunk(
+                ChunkFlags::LONER,
+                "",
+                vec![link("GGCL:2", 0), link("GGCL:3", 0), link("GLXF:9", 1)],
+                b"action",
+            ),
+        );
+        writer.insert(
+            id("GGCL:2"),
+            chunk(
+                ChunkFlags::PACKED,
+                "",
+                vec![link("GGCL:3", 0)],
+                b"not packed",
+            ),
+        );
+        writer.insert(
+            id("GGCL:3"),
+            chunk(ChunkFlags::empty(), "", vec![link("GGCL:2", 0)], &cells),
+        );
+        for (chunk_id, data) in [("MVIE:4", &forest[..]), ("MVIE:5", b"not a chunky file")] {
+            writer.insert(
+                id(chunk_id),
+                chunk(ChunkFlags::LONER | ChunkFlags::FOREST, "", Vec::new(), data),
+            );
+        }
+
+        let output = write(&writer);
+        let file = ChunkyFile::load(&output).unwrap();
+
+        let mut problems: Vec<_> = check(&file).iter().map(|p| p.to_string()).collect();
+        let bad_forest = problems.pop().unwrap();
+        assert!(bad_forest.starts_with("MVIE:5 can't be read as a forest: "));
+        assert_eq!(
+            problems,
+            vec![
+                "ACTN:1 child 1 is GLXF:9, which doesn't exist",
+                "ACTN:1 has several GGCL children with child ID 0",
+                "cycle: GGCL:2 -> GGCL:3 -> GGCL:2",
+                "GGCL:2 is flagged PACKED but has no codec",
+                "GGCL:3 is KCDC data but isn't flagged PACKED",
+                "in forest MVIE:4: TMPL:1 child 0 is ACTN:2, which doesn't exist",
+            ],
+        );
+
+        let problems: Vec<_> = check_chunks(&file, &[id("GGCL:3")])
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        assert_eq!(
+            problems,
+            vec![
+                "cycle: GGCL:2 -> GGCL:3 -> GGCL:2",
+                "GGCL:3 is KCDC data but isn't flagged PACKED",
+            ],
+        );
+    }
+}
@@ -1,11 +1,9 @@
-use std::mem;
-
-use anyhow::{bail, Result};
 use byteorder::ByteOrder;
 use zerocopy::{FromBytes, U16, U32};
 
 use crate::{
     brender::Scalar,
+    error::Result,
     ggf::{Group, GroupOnFile},
     order::Loader,
 };
@@ -64,24 +62,17 @@ impl<'a> Loader<'a> for AnimationCells {
         let group = Group::from_file(&on_file, full_input)?;
 
         let mut cells = Vec::with_capacity(group.len());
-        for v in group.iter() {
-            let Some(cel) = CelOnFile::<O>::read_from(v.fixed) else {
-                bail!("Invalid fixed item size");
-            };
-            let mut parts = Vec::with_capacity(v.variable.len() / mem::size_of::<CpsOnFile<O>>());
-            let mut cps_data = v.variable;
-            while !cps_data.is_empty() {
-                let Some(cps) = CpsOnFile::<O>::read_from_prefix(cps_data) else {
-                    bail!("EOF in CPS");
-                };
-                cps_data = &cps_data[mem::size_of::<CpsOnFile<O>>()..];
-                parts.push(CellPartSpec {
+        for v in group.typed::<CelOnFile<O>, CpsOnFile<O>>()? {
+            let v = v?;
+            let parts = v
+                .variable
+                .map(|cps| CellPartSpec {
                     model_id: Some(cps.model_id.get()).filter(|v| *v != 65535),
                     matrix_id: cps.matrix_id.get(),
-                });
-            }
+                })
+                .collect();
             cells.push(Cell {
-                dwr: cel.dwr.into(),
+                dwr: v.fixed.dwr.into(),
                 parts,
             });
         }
@@ -1,8 +1,10 @@
-use anyhow::{bail, Result};
-use byteorder::{ByteOrder, ReadBytesExt};
-use zerocopy::{FromBytes, U32};
+use std::mem;
+
+use byteorder::ByteOrder;
+use zerocopy::U32;
 
 use crate::{
+    error::{Error, Result},
     ggf::{Group, GroupOnFile},
     order::Loader,
 };
@@ -30,15 +32,16 @@ impl<'a> Loader<'a> for Costumes {
         let group = Group::from_file(&on_file, full_input)?;
 
         let mut part_sets = Vec::with_capacity(group.len());
-        for mut v in group.iter() {
-            let Some(entries) = U32::<O>::read_from(v.fixed) else {
-                bail!("Invalid fixed item size");
-            };
-            let mut set_materials = Vec::with_capacity(entries.get() as usize);
-            for _ in 0..entries.get() {
-                set_materials.push(v.variable.read_u32::<O>()?);
+        for v in group.typed::<U32<O>, U32<O>>()? {
+            let v = v?;
+            let entries = v.fixed.get() as usize;
+            if v.variable.len() < entries {
+                return Err(Error::truncated(
+                    "costume materials",
+                    v.offset + mem::size_of::<U32<O>>(),
+                ));
             }
-            part_sets.push(set_materials);
+            part_sets.push(v.variable.take(entries).map(|m| m.get()).collect());
         }
 
         Ok(Costumes { part_sets })
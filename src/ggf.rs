@@ -1,9 +1,13 @@
-use std::{marker::PhantomData, mem};
+use std::{iter::FusedIterator, marker::PhantomData, mem};
 
-use anyhow::{bail, Result};
 use byteorder::ByteOrder;
 use zerocopy::{FromBytes, U16, U32};
 
+use crate::{
+    error::{Error, Result},
+    glf::Items,
+};
+
 #[derive(Debug, FromBytes)]
 #[repr(C)]
 pub struct GroupOnFile<O>
@@ -46,8 +50,10 @@ where
     where
         O: ByteOrder,
     {
-        let Some(remainder) = full_input.get(mem::size_of::<GroupOnFile<O>>()..mem::size_of::<GroupOnFile<O>>()+mem::size_of::<Loc<O>>()*header.length_entries.get() as usize+header.data_length_bytes.get() as usize) else {
-            bail!("EOF in group");
+        let size = mem::size_of::<Loc<O>>() * header.length_entries.get() as usize
+            + header.data_length_bytes.get() as usize;
+        let Some(remainder) = full_input.get(mem::size_of::<GroupOnFile<O>>()..mem::size_of::<GroupOnFile<O>>() + size) else {
+            return Err(Error::truncated("group", full_input.len()));
         };
 
         let (data, entries) = remainder.split_at(header.data_length_bytes.get() as usize);
@@ -60,25 +66,58 @@ where
         })
     }
 
-    pub fn get(&self, index: usize) -> Option<GroupEntry> {
-        let loc = Loc::<O>::read_from(
-            &self.entries[index * mem::size_of::<Loc<O>>()..(index + 1) * mem::size_of::<Loc<O>>()],
-        )?;
-        let data = &self.data
-            [loc.offset.get() as usize..loc.offset.get() as usize + loc.length.get() as usize];
-        let (fixed, variable) = data.split_at(self.fixed);
-
-        Some(GroupEntry { fixed, variable })
+    pub fn get(&self, index: usize) -> Result<GroupEntry<'a>> {
+        let Some(loc) = self
+            .entries
+            .get(index * mem::size_of::<Loc<O>>()..(index + 1) * mem::size_of::<Loc<O>>())
+            .and_then(Loc::<O>::read_from)
+        else {
+            return Err(Error::invalid(
+                format!("Group entry {index} out of range"),
+                mem::size_of::<GroupOnFile<O>>() + self.data.len(),
+            ));
+        };
+        entry(self.data, self.fixed, index, &loc)
     }
 
     pub fn iter(&self) -> GroupItems<'a, O> {
         self.into_iter()
     }
 
+    /// The entries with their fixed parts read as an `F` and their variable parts as `V`s, once
+    /// the fixed part is checked to be the size of an `F`. `V` can't be zero sized.
+    pub fn typed<F, V>(&self) -> Result<TypedGroupItems<'a, O, F, V>>
+    where
+        F: FromBytes,
+        V: FromBytes,
+    {
+        if mem::size_of::<V>() == 0 {
+            return Err(Error::unsupported("zero sized group items", 0));
+        }
+        if !self.is_empty() && self.fixed != mem::size_of::<F>() {
+            return Err(Error::invalid(
+                format!(
+                    "Invalid fixed part size ({:#x} != {:#x})",
+                    self.fixed,
+                    mem::size_of::<F>()
+                ),
+                mem::offset_of!(GroupOnFile<O>, fixed),
+            ));
+        }
+        Ok(TypedGroupItems {
+            items: self.iter(),
+            _phantom: PhantomData,
+        })
+    }
+
     pub fn len(&self) -> usize {
         self.entries.len() / mem::size_of::<Loc<O>>()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     pub fn byte_order(on_file: &GroupOnFile<O>) -> u16
     where
         O: ByteOrder,
@@ -91,7 +130,7 @@ impl<'a, O> IntoIterator for &Group<'a, O>
 where
     O: ByteOrder,
 {
-    type Item = GroupEntry<'a>;
+    type Item = Result<GroupEntry<'a>>;
 
     type IntoIter = GroupItems<'a, O>;
 
@@ -100,6 +139,7 @@ where
             fixed: self.fixed,
             entries: self.entries,
             data: self.data,
+            index: 0,
             _phantom: PhantomData,
         }
     }
@@ -112,11 +152,16 @@ where
     fixed: usize,
     entries: &'a [u8],
     data: &'a [u8],
+    /// Index of the first entry in `entries`.
+    index: usize,
     _phantom: PhantomData<O>,
 }
 
 #[derive(Debug)]
 pub struct GroupEntry<'a> {
+    pub index: usize,
+    /// Where the entry starts in the group's chunk.
+    pub offset: usize,
     pub fixed: &'a [u8],
     pub variable: &'a [u8],
 }
@@ -125,7 +170,7 @@ impl<'a, O> Iterator for GroupItems<'a, O>
 where
     O: ByteOrder,
 {
-    type Item = GroupEntry<'a>;
+    type Item = Result<GroupEntry<'a>>;
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.entries.is_empty() {
@@ -135,12 +180,9 @@ where
         let (first, rest) = self.entries.split_at(mem::size_of::<Loc<O>>());
         let loc = Loc::<O>::read_from(first).unwrap();
         self.entries = rest;
+        self.index += 1;
 
-        let data = &self.data
-            [loc.offset.get() as usize..loc.offset.get() as usize + loc.length.get() as usize];
-        let (fixed, variable) = data.split_at(self.fixed);
-
-        Some(GroupEntry { fixed, variable })
+        Some(entry(self.data, self.fixed, self.index - 1, &loc))
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
@@ -164,10 +206,168 @@ where
         let loc = Loc::<O>::read_from(last).unwrap();
         self.entries = rest;
 
-        let data = &self.data
-            [loc.offset.get() as usize..loc.offset.get() as usize + loc.length.get() as usize];
-        let (fixed, variable) = data.split_at(self.fixed);
+        let index = self.index + self.entries.len() / mem::size_of::<Loc<O>>();
+        Some(entry(self.data, self.fixed, index, &loc))
+    }
+}
+
+fn entry<'a, O>(data: &'a [u8], fixed: usize, index: usize, loc: &Loc<O>) -> Result<GroupEntry<'a>>
+where
+    O: ByteOrder,
+{
+    let offset = loc.offset.get() as usize;
+    let length = loc.length.get() as usize;
+    let position = mem::size_of::<GroupOnFile<O>>() + offset;
+    let Some(data) = data.get(offset..offset + length) else {
+        return Err(Error::invalid(
+            format!("Group entry {index} (+{length:#x}) is out of bounds"),
+            position,
+        ));
+    };
+    if data.len() < fixed {
+        return Err(Error::invalid(
+            format!("Group entry {index} is smaller than its fixed part ({length:#x} < {fixed:#x})"),
+            position,
+        ));
+    }
+    let (fixed, variable) = data.split_at(fixed);
+
+    Ok(GroupEntry {
+        index,
+        offset: position,
+        fixed,
+        variable,
+    })
+}
+
+/// Iterates over a group's entries as read by [`Group::typed`].
+pub struct TypedGroupItems<'a, O, F, V>
+where
+    O: ByteOrder,
+{
+    items: GroupItems<'a, O>,
+    _phantom: PhantomData<(F, V)>,
+}
+
+pub struct TypedGroupEntry<'a, F, V> {
+    pub index: usize,
+    /// Where the entry starts in the group's chunk.
+    pub offset: usize,
+    pub fixed: F,
+    pub variable: Items<'a, V>,
+}
+
+impl<'a, F, V> TypedGroupEntry<'a, F, V>
+where
+    F: FromBytes,
+    V: FromBytes,
+{
+    fn new(entry: GroupEntry<'a>) -> Result<Self> {
+        let size = mem::size_of::<V>();
+        if !entry.variable.len().is_multiple_of(size) {
+            return Err(Error::invalid(
+                format!(
+                    "Group entry {} has a partial item ({:#x} % {size:#x})",
+                    entry.index,
+                    entry.variable.len()
+                ),
+                entry.offset + entry.fixed.len(),
+            ));
+        }
+        Ok(TypedGroupEntry {
+            index: entry.index,
+            offset: entry.offset,
+            fixed: F::read_from(entry.fixed).unwrap(),
+            variable: Items::new(entry.variable),
+        })
+    }
+}
+
+impl<'a, O, F, V> Iterator for TypedGroupItems<'a, O, F, V>
+where
+    O: ByteOrder,
+    F: FromBytes,
+    V: FromBytes,
+{
+    type Item = Result<TypedGroupEntry<'a, F, V>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.items.next()?.and_then(TypedGroupEntry::new))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.items.size_hint()
+    }
+}
+
+impl<'a, O, F, V> DoubleEndedIterator for TypedGroupItems<'a, O, F, V>
+where
+    O: ByteOrder,
+    F: FromBytes,
+    V: FromBytes,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        Some(self.items.next_back()?.and_then(TypedGroupEntry::new))
+    }
+}
+
+impl<'a, O, F, V> ExactSizeIterator for TypedGroupItems<'a, O, F, V>
+where
+    O: ByteOrder,
+    F: FromBytes,
+    V: FromBytes,
+{
+}
+
+impl<'a, O, F, V> FusedIterator for TypedGroupItems<'a, O, F, V>
+where
+    O: ByteOrder,
+    F: FromBytes,
+    V: FromBytes,
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::LittleEndian;
+
+    use super::*;
+
+    #[test]
+    fn typed_entries() {
+        // Two entries with a u16 fixed part and u16s after it, the second one a byte short.
+        let mut data = Vec::new();
+        for value in [1u16, 0] {
+            data.extend(value.to_le_bytes());
+        }
+        for value in [2u32, 9, 0, 2] {
+            data.extend(value.to_le_bytes());
+        }
+        for value in [7u16, 8, 9, 10, 11] {
+            data.extend(value.to_le_bytes());
+        }
+        data.pop();
+        for value in [0u32, 6, 6, 3] {
+            data.extend(value.to_le_bytes());
+        }
+        let header = GroupOnFile::<LittleEndian>::read_from_prefix(&data[..]).unwrap();
+        let group = Group::from_file(&header, &data).unwrap();
+
+        let mut items = group
+            .typed::<U16<LittleEndian>, U16<LittleEndian>>()
+            .unwrap();
+        assert_eq!(items.len(), 2);
+        let first = items.next().unwrap().unwrap();
+        assert_eq!(first.fixed.get(), 7);
+        assert_eq!(first.variable.map(|v| v.get()).collect::<Vec<_>>(), [8, 9]);
+        let error = items.next().unwrap().err().unwrap();
+        assert_eq!(error.offset, 0x14 + 8);
 
-        Some(GroupEntry { fixed, variable })
+        let error = group
+            .typed::<U32<LittleEndian>, U16<LittleEndian>>()
+            .err()
+            .unwrap();
+        assert_eq!(error.offset, 0x10);
+        assert!(group.typed::<U16<LittleEndian>, ()>().is_err());
     }
 }
@@ -1,10 +1,12 @@
-use std::{mem, ops::Index};
+use std::{iter::FusedIterator, marker::PhantomData, mem, ops::Index, slice::ChunksExact};
 
-use anyhow::{bail, Result};
-use byteorder::ByteOrder;
+use byteorder::{ByteOrder, LittleEndian};
 use zerocopy::{FromBytes, U16, U32};
 
-use crate::order::Loader;
+use crate::{
+    error::{Error, Result},
+    order::Loader,
+};
 
 #[derive(Debug, FromBytes)]
 #[repr(C)]
@@ -28,8 +30,9 @@ impl<'a> List<'a> {
     where
         O: ByteOrder,
     {
-        let Some(data) = full_input.get(mem::size_of::<ListOnFile<O>>()..mem::size_of::<ListOnFile<O>>()+header.entry_size.get() as usize * header.length.get() as usize) else {
-            bail!("EOF in list");
+        let size = header.entry_size.get() as usize * header.length.get() as usize;
+        let Some(data) = full_input.get(mem::size_of::<ListOnFile<O>>()..mem::size_of::<ListOnFile<O>>() + size) else {
+            return Err(Error::truncated("list", full_input.len()));
         };
 
         Ok(List {
@@ -39,17 +42,42 @@ impl<'a> List<'a> {
     }
 
     pub fn get(&self, index: usize) -> Option<&[u8]> {
-        self.data
-            .get(self.entry_size as usize * index..self.entry_size as usize * (index + 1))
+        let start = (self.entry_size as usize).checked_mul(index)?;
+        self.data.get(start..start.checked_add(self.entry_size as usize)?)
     }
 
     pub fn iter(&self) -> ListItems<'a> {
         self.into_iter()
     }
 
+    /// The entries read as `T`s, once they're checked to be the size of one.
+    pub fn typed<T>(&self) -> Result<Items<'a, T>>
+    where
+        T: FromBytes,
+    {
+        if mem::size_of::<T>() == 0 {
+            return Err(Error::unsupported("zero sized list items", 0));
+        }
+        if !self.data.is_empty() && self.entry_size as usize != mem::size_of::<T>() {
+            return Err(Error::invalid(
+                format!(
+                    "Invalid list item size ({:#x} != {:#x})",
+                    self.entry_size,
+                    mem::size_of::<T>()
+                ),
+                mem::offset_of!(ListOnFile<LittleEndian>, entry_size),
+            ));
+        }
+        Ok(Items::new(self.data))
+    }
+
     pub fn len(&self) -> usize {
         self.data.len() / self.entry_size as usize
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 }
 
 impl<'a> Index<usize> for List<'a> {
@@ -111,6 +139,55 @@ impl<'a> DoubleEndedIterator for ListItems<'a> {
     }
 }
 
+/// Reads `T`s packed one after another in data that's known to hold a whole number of them.
+pub struct Items<'a, T> {
+    chunks: ChunksExact<'a, u8>,
+    _phantom: PhantomData<T>,
+}
+
+impl<'a, T> Items<'a, T>
+where
+    T: FromBytes,
+{
+    /// Reads `data`, which must be a multiple of the size of `T` long. `T` can't be zero sized.
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        let chunks = data.chunks_exact(mem::size_of::<T>());
+        debug_assert!(chunks.remainder().is_empty());
+        Items {
+            chunks,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<'a, T> Iterator for Items<'a, T>
+where
+    T: FromBytes,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.chunks.next().map(|c| T::read_from(c).unwrap())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.chunks.size_hint()
+    }
+}
+
+impl<'a, T> DoubleEndedIterator for Items<'a, T>
+where
+    T: FromBytes,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.chunks.next_back().map(|c| T::read_from(c).unwrap())
+    }
+}
+
+impl<'a, T> ExactSizeIterator for Items<'a, T> where T: FromBytes {}
+
+impl<'a, T> FusedIterator for Items<'a, T> where T: FromBytes {}
+
 impl<'a> Loader<'a> for List<'a> {
     type OnFile<O> = ListOnFile<O>
     where
@@ -130,3 +207,29 @@ impl<'a> Loader<'a> for List<'a> {
         List::from_file(&header, full_input)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use byteorder::BigEndian;
+
+    use super::*;
+
+    #[test]
+    fn typed_entries() {
+        let mut data = vec![0x00, 0x01, 0x00, 0x00];
+        data.extend(2u32.to_be_bytes());
+        data.extend(3u32.to_be_bytes());
+        data.extend([0x00, 0x05, 0xff, 0xfe, 0x01, 0x00]);
+        let list = List::load(&data).unwrap();
+
+        let items = list.typed::<U16<BigEndian>>().unwrap();
+        assert_eq!(items.len(), 3);
+        assert_eq!(
+            items.rev().map(|v| v.get()).collect::<Vec<_>>(),
+            [0x0100, 0xfffe, 0x0005]
+        );
+        let error = list.typed::<U32<BigEndian>>().err().unwrap();
+        assert_eq!(error.offset, 4);
+        assert!(list.typed::<()>().is_err());
+    }
+}
@@ -1,8 +1,8 @@
-use anyhow::{bail, Result};
 use byteorder::ByteOrder;
-use zerocopy::{FromBytes, U16};
+use zerocopy::U16;
 
 use crate::{
+    error::Result,
     glf::{List, ListOnFile},
     order::Loader,
 };
@@ -27,15 +27,10 @@ impl<'a> Loader<'a> for Armature {
     where
         O: ByteOrder,
     {
-        let list = List::from_file(&on_file, full_input)?;
-
-        let mut parents = Vec::with_capacity(list.len());
-        for v in list.iter() {
-            let Some(v) = U16::<O>::read_from(v) else {
-                bail!("Invalid list item size");
-            };
-            parents.push(v.get())
-        }
+        let parents = List::from_file(&on_file, full_input)?
+            .typed::<U16<O>>()?
+            .map(|v| v.get())
+            .collect();
 
         Ok(Armature { parents })
     }
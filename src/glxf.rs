@@ -1,10 +1,10 @@
-use anyhow::{bail, Result};
 use byteorder::ByteOrder;
 use nalgebra::{Affine3, Matrix4};
 use zerocopy::FromBytes;
 
 use crate::{
     brender::Scalar,
+    error::Result,
     glf::{List, ListOnFile},
     order::Loader,
 };
@@ -41,10 +41,7 @@ impl<'a> Loader<'a> for AnimationTransforms {
         let list = List::from_file(&on_file, full_input)?;
 
         let mut transforms = Vec::with_capacity(list.len());
-        for v in list.iter() {
-            let Some(v) = Mat34OnFile::<O>::read_from(v) else {
-                bail!("Invalid list item size");
-            };
+        for v in list.typed::<Mat34OnFile<O>>()? {
             transforms.push(Affine3::from_matrix_unchecked(Matrix4::new(
                 v.m[0][0].into(),
                 v.m[1][0].into(),
@@ -0,0 +1,233 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    io::{self, Write},
+};
+
+use serde_json::{json, Value};
+
+use crate::chunky::{ChunkFlags, ChunkId, ChunkyFile, IndexEntry};
+
+/// The chunks to include in a graph: the whole file, or just what can be reached from `root`
+/// through child links, following at most `depth` links if given.
+pub fn select<'f, 'a>(
+    file: &'f ChunkyFile<'a>,
+    root: Option<&ChunkId>,
+    depth: Option<usize>,
+) -> Vec<&'f IndexEntry<'a>> {
+    let mut entries: Vec<_> = match root {
+        None => file.index.values().collect(),
+        Some(root) => {
+            // Breadth first, so each chunk is reached by its shortest path from the root.
+            let mut visited = HashSet::from([*root]);
+            let mut pending = VecDeque::from([(*root, 0)]);
+            let mut entries = Vec::new();
+            while let Some((id, distance)) = pending.pop_front() {
+                let Some(entry) = file.index.get(&id) else {
+                    continue;
+                };
+                entries.push(entry);
+                if depth.is_some_and(|depth| distance >= depth) {
+                    continue;
+                }
+                for child in &entry.children {
+                    if visited.insert(child.chunk_id) {
+                        pending.push_back((child.chunk_id, distance + 1));
+                    }
+                }
+            }
+            entries
+        }
+    };
+    entries.sort_unstable_by_key(|e| e.id);
+    entries
+}
+
+fn flag_names(flags: ChunkFlags) -> Vec<&'static str> {
+    flags.iter_names().map(|(name, _)| name).collect()
+}
+
+/// Writes the chunks and their child links as a Graphviz digraph.
+/// Links to chunks that aren't in the index point at nodes drawn with a dashed outline, and links
+/// to chunks in `file` that `entries` leaves out point at grey dotted nodes.
+pub fn write_dot<W>(file: &ChunkyFile, entries: &[&IndexEntry], mut output: W) -> io::Result<()>
+where
+    W: Write,
+{
+    let known: HashSet<_> = entries.iter().map(|e| e.id).collect();
+    writeln!(output, "digraph chunks {{")?;
+    writeln!(output, "    node [shape=box, fontname=monospace];")?;
+    for entry in entries {
+        let mut label = entry.id.to_string();
+        if !entry.name.is_empty() {
+            label.push_str(&format!("\\n{}", escape(&entry.name)));
+        }
+        if !entry.flags.is_empty() {
+            label.push_str(&format!("\\n{}", entry.flags));
+        }
+        writeln!(output, "    \"{}\" [label=\"{label}\"];", entry.id)?;
+    }
+    let mut outside = HashSet::new();
+    for entry in entries {
+        for child in &entry.children {
+            if !known.contains(&child.chunk_id) && outside.insert(child.chunk_id) {
+                let style = if file.index.contains_key(&child.chunk_id) {
+                    "style=dotted, color=grey, fontcolor=grey"
+                } else {
+                    "style=dashed"
+                };
+                writeln!(output, "    \"{}\" [{style}];", child.chunk_id)?;
+            }
+            writeln!(
+                output,
+                "    \"{}\" -> \"{}\" [label=\"{}\"];",
+                entry.id, child.chunk_id, child.child_id,
+            )?;
+        }
+    }
+    writeln!(output, "}}")
+}
+
+/// Escapes text for use inside a quoted DOT string.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("\\n"),
+            c if c.is_control() => escaped.extend(c.escape_default()),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The chunks and their child links as JSON, for scripts.
+pub fn to_json(entries: &[&IndexEntry]) -> Value {
+    let chunks: Vec<_> = entries
+        .iter()
+        .map(|entry| {
+            json!({
+                "tag": entry.id.tag.to_string(),
+                "number": entry.id.number.get(),
+                "name": entry.name,
+                "flags": flag_names(entry.flags),
+                "length": entry.length,
+                "children": entry
+                    .children
+                    .iter()
+                    .map(|c| json!({
+                        "tag": c.chunk_id.tag.to_string(),
+                        "number": c.chunk_id.number.get(),
+                        "child_id": c.child_id,
+                    }))
+                    .collect::<Vec<_>>(),
+            })
+        })
+        .collect();
+    json!({ "chunks": chunks })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunky::testing::{chunk, file, id, link},
+        order::Loader,
+    };
+
+    fn sample() -> Vec<u8> {
+        file([
+            (
+                "TMPL:1",
+                chunk(ChunkFlags::LONER, "Willy", vec![link("ACTN:2", 0)], b""),
+            ),
+            (
+                "ACTN:2",
+                chunk(
+                    ChunkFlags::empty(),
+                    "",
+                    vec![link("GGCL:3", 0), link("GLXF:9", 1)],
+                    b"",
+                ),
+            ),
+            ("GGCL:3", chunk(ChunkFlags::empty(), "", Vec::new(), b"")),
+            ("TMPL:4", chunk(ChunkFlags::LONER, "", Vec::new(), b"")),
+        ])
+    }
+
+    #[test]
+    fn select_reachable() {
+        let output = sample();
+        let file = ChunkyFile::load(&output).unwrap();
+        let select = |root: Option<&str>, depth| -> Vec<ChunkId> {
+            select(&file, root.map(id).as_ref(), depth)
+                .iter()
+                .map(|e| e.id)
+                .collect()
+        };
+        assert_eq!(select(None, None).len(), 4);
+        assert_eq!(
+            select(Some("TMPL:1"), None),
+            [id("ACTN:2"), id("GGCL:3"), id("TMPL:1")]
+        );
+        assert_eq!(
+            select(Some("TMPL:1"), Some(1)),
+            [id("ACTN:2"), id("TMPL:1")]
+        );
+        assert_eq!(select(Some("TMPL:1"), Some(0)), [id("TMPL:1")]);
+    }
+
+    #[test]
+    fn dot() {
+        let output = sample();
+        let file = ChunkyFile::load(&output).unwrap();
+        let entries = select(&file, Some(&id("TMPL:1")), Some(1));
+        let mut dot = Vec::new();
+        write_dot(&file, &entries, &mut dot).unwrap();
+        assert_eq!(
+            String::from_utf8(dot).unwrap(),
+            r#"digraph chunks {
+    node [shape=box, fontname=monospace];
+    "ACTN:2" [label="ACTN:2"];
+    "TMPL:1" [label="TMPL:1\nWilly\nLONER"];
+    "GGCL:3" [style=dotted, color=grey, fontcolor=grey];
+    "ACTN:2" -> "GGCL:3" [label="0"];
+    "GLXF:9" [style=dashed];
+    "ACTN:2" -> "GLXF:9" [label="1"];
+    "TMPL:1" -> "ACTN:2" [label="0"];
+}
+"#,
+        );
+    }
+
+    #[test]
+    fn json() {
+        let output = sample();
+        let file = ChunkyFile::load(&output).unwrap();
+        let entries = select(&file, Some(&id("ACTN:2")), Some(0));
+        assert_eq!(
+            to_json(&entries),
+            json!({
+                "chunks": [{
+                    "tag": "ACTN",
+                    "number": 2,
+                    "name": "",
+                    "flags": [],
+                    "length": 0,
+                    "children": [
+                        { "tag": "GGCL", "number": 3, "child_id": 0 },
+                        { "tag": "GLXF", "number": 9, "child_id": 1 },
+                    ],
+                }],
+            }),
+        );
+    }
+
+    #[test]
+    fn escape_dot_strings() {
+        assert_eq!(escape("say \"hi\"\\\n"), "say \\\"hi\\\"\\\\\\n");
+    }
+}
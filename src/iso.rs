@@ -0,0 +1,392 @@
+use std::collections::HashSet;
+
+use crate::error::{Error, Result};
+
+const SECTOR_SIZE: usize = 2048;
+/// Volume descriptors start after the system area.
+const FIRST_DESCRIPTOR: usize = 16;
+const DESCRIPTOR_PRIMARY: u8 = 1;
+const DESCRIPTOR_SUPPLEMENTARY: u8 = 2;
+const DESCRIPTOR_TERMINATOR: u8 = 255;
+/// Escape sequences marking a supplementary volume descriptor as Joliet, for UCS-2 levels 1 to 3.
+const JOLIET_ESCAPES: [&[u8]; 3] = [b"%/@", b"%/C", b"%/E"];
+const ROOT_RECORD_OFFSET: usize = 156;
+const FLAG_DIRECTORY: u8 = 0x02;
+
+/// Extensions of the chunky files 3DMM ships on its discs.
+pub const CHUNKY_EXTENSIONS: [&str; 3] = ["3cn", "3th", "chk"];
+
+/// An ISO 9660 CD-ROM image held in memory.
+///
+/// Files on a disc are stored contiguously, so their contents are borrowed straight from the
+/// image. Joliet names are used when the disc has them, since the plain ISO 9660 names are
+/// truncated to 8.3 and upper cased.
+pub struct IsoImage<'a> {
+    data: &'a [u8],
+    block_size: usize,
+    root: Record,
+    pub joliet: bool,
+}
+
+/// A file found in an [`IsoImage`].
+pub struct IsoFile<'a> {
+    /// The path from the root of the disc, separated by `/`.
+    pub path: String,
+    /// Where the file starts in the image.
+    pub offset: usize,
+    pub data: &'a [u8],
+}
+
+/// The files on a disc, and what kept the rest from being listed.
+pub struct Listing<'a> {
+    pub files: Vec<IsoFile<'a>>,
+    /// The path of each file or directory that couldn't be read, with what was wrong with it.
+    pub errors: Vec<(String, Error)>,
+}
+
+struct Record {
+    name: String,
+    extent: usize,
+    length: usize,
+    directory: bool,
+}
+
+impl<'a> IsoImage<'a> {
+    pub fn open(data: &'a [u8]) -> Result<Self> {
+        let mut primary = None;
+        let mut joliet = None;
+        for sector in FIRST_DESCRIPTOR.. {
+            let position = sector * SECTOR_SIZE;
+            let Some(descriptor) = data.get(position..position + SECTOR_SIZE) else {
+                return Err(Error::truncated("volume descriptors", data.len()));
+            };
+            if &descriptor[1..6] != b"CD001" {
+                return Err(Error::invalid("Not an ISO 9660 image", position + 1));
+            }
+            match descriptor[0] {
+                DESCRIPTOR_PRIMARY => primary = primary.or(Some(position)),
+                DESCRIPTOR_SUPPLEMENTARY
+                    if JOLIET_ESCAPES
+                        .iter()
+                        .any(|e| descriptor[88..].starts_with(e)) =>
+                {
+                    joliet = joliet.or(Some(position))
+                }
+                DESCRIPTOR_TERMINATOR => break,
+                _ => {}
+            }
+        }
+
+        let Some(position) = joliet.or(primary) else {
+            return Err(Error::invalid(
+                "No primary volume descriptor",
+                FIRST_DESCRIPTOR * SECTOR_SIZE,
+            ));
+        };
+        let block_size = u16::from_le_bytes([data[position + 128], data[position + 129]]) as usize;
+        if !block_size.is_power_of_two() || block_size > SECTOR_SIZE {
+            return Err(Error::unsupported(
+                format!("block size {block_size}"),
+                position + 128,
+            ));
+        }
+        let joliet = joliet.is_some();
+        let root = read_record(data, position + ROOT_RECORD_OFFSET, false)?;
+        Ok(IsoImage {
+            data,
+            block_size,
+            root,
+            joliet,
+        })
+    }
+
+    /// Every file on the disc that can be read, each directory's files before those of its
+    /// subdirectories. Damaged records and extents are skipped, so one bad file doesn't hide the
+    /// rest.
+    pub fn files(&self) -> Listing<'a> {
+        let mut files = Vec::new();
+        let mut errors = Vec::new();
+        let mut visited = HashSet::new();
+        let mut pending = vec![(String::new(), self.root.extent, self.root.length)];
+        while let Some((path, extent, length)) = pending.pop() {
+            // A corrupt image can make a directory its own descendant.
+            if !visited.insert(extent) {
+                continue;
+            }
+            let mut children = match self.read_directory(extent, length) {
+                Ok((children, record_errors)) => {
+                    errors.extend(record_errors.into_iter().map(|e| (path.clone(), e)));
+                    children
+                }
+                Err(error) => {
+                    errors.push((path, error));
+                    continue;
+                }
+            };
+            children.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+            let mut directories = Vec::new();
+            for record in children {
+                let path = if path.is_empty() {
+                    record.name
+                } else {
+                    format!("{path}/{}", record.name)
+                };
+                if record.directory {
+                    directories.push((path, record.extent, record.length));
+                } else {
+                    match self.extent(record.extent, record.length) {
+                        Ok(data) => files.push(IsoFile {
+                            path,
+                            offset: record.extent * self.block_size,
+                            data,
+                        }),
+                        Err(error) => errors.push((path, error)),
+                    }
+                }
+            }
+            pending.extend(directories.into_iter().rev());
+        }
+        Listing { files, errors }
+    }
+
+    /// Finds a file by its path from the root of the disc, ignoring case as Windows does. If it
+    /// isn't there, returns the error for it or for a damaged directory it could be in, if any.
+    pub fn find(&self, path: &str) -> Result<Option<IsoFile<'a>>> {
+        let path = path.trim_matches('/');
+        let Listing { files, errors } = self.files();
+        if let Some(file) = files
+            .into_iter()
+            .find(|f| f.path.eq_ignore_ascii_case(path))
+        {
+            return Ok(Some(file));
+        }
+        let lower = path.to_ascii_lowercase();
+        let error = errors.into_iter().find(|(p, _)| {
+            let p = p.to_ascii_lowercase();
+            p.is_empty() || lower == p || lower.starts_with(&format!("{p}/"))
+        });
+        match error {
+            Some((_, error)) => Err(error),
+            None => Ok(None),
+        }
+    }
+
+    /// The chunky files on the disc, found by their extensions, with every error from listing it.
+    pub fn chunky_files(&self) -> Listing<'a> {
+        let mut listing = self.files();
+        listing.files.retain(|f| {
+            f.path.rsplit_once('.').is_some_and(|(_, extension)| {
+                CHUNKY_EXTENSIONS
+                    .iter()
+                    .any(|e| e.eq_ignore_ascii_case(extension))
+            })
+        });
+        listing
+    }
+
+    fn extent(&self, extent: usize, length: usize) -> Result<&'a [u8]> {
+        let start = extent * self.block_size;
+        self.data
+            .get(start..start + length)
+            .ok_or_else(|| Error::truncated("file extent", self.data.len()))
+    }
+
+    /// Reads a directory's records, returning what was wrong with those that couldn't be read
+    /// alongside the rest.
+    fn read_directory(&self, extent: usize, length: usize) -> Result<(Vec<Record>, Vec<Error>)> {
+        let data = self.extent(extent, length)?;
+        let start = extent * self.block_size;
+        let mut records = Vec::new();
+        let mut errors = Vec::new();
+        let mut offset = 0;
+        while offset < data.len() {
+            // Records don't cross sector boundaries; a zero length pads out the sector.
+            if data[offset] == 0 {
+                offset = (offset / SECTOR_SIZE + 1) * SECTOR_SIZE;
+                continue;
+            }
+            match read_record(self.data, start + offset, self.joliet) {
+                // Skip the entries for the directory itself and its parent.
+                Ok(record) if record.name.is_empty() => {}
+                Ok(record) => records.push(record),
+                Err(error) => errors.push(error),
+            }
+            offset += data[offset] as usize;
+        }
+        Ok((records, errors))
+    }
+}
+
+/// Reads the directory record at `position` in the image.
+fn read_record(data: &[u8], position: usize, joliet: bool) -> Result<Record> {
+    let Some(&length) = data.get(position) else {
+        return Err(Error::truncated("directory record", data.len()));
+    };
+    let Some(record) = data.get(position..position + length as usize) else {
+        return Err(Error::truncated("directory record", data.len()));
+    };
+    if record.len() < 34 || record.len() < 33 + record[32] as usize {
+        return Err(Error::invalid("Directory record too short", position));
+    }
+    let u32_at = |i: usize| u32::from_le_bytes(record[i..i + 4].try_into().unwrap()) as usize;
+
+    let name = &record[33..33 + record[32] as usize];
+    let name = if name == [0] || name == [1] {
+        String::new()
+    } else if joliet {
+        let units: Vec<_> = name
+            .chunks_exact(2)
+            .map(|c| u16::from_be_bytes([c[0], c[1]]))
+            .collect();
+        String::from_utf16_lossy(&units)
+    } else {
+        String::from_utf8_lossy(name).into_owned()
+    };
+    // Drop the version number, and the dot ISO 9660 adds to names without an extension.
+    let name = name.split_once(';').map_or(&name[..], |(name, _)| name);
+    let name = name.strip_suffix('.').unwrap_or(name).to_owned();
+
+    Ok(Record {
+        name,
+        extent: u32_at(2),
+        length: u32_at(10),
+        directory: record[25] & FLAG_DIRECTORY != 0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(name: &[u8], extent: u32, length: u32, directory: bool) -> Vec<u8> {
+        let mut record = vec![0; 33];
+        record[2..6].copy_from_slice(&extent.to_le_bytes());
+        record[6..10].copy_from_slice(&extent.to_be_bytes());
+        record[10..14].copy_from_slice(&length.to_le_bytes());
+        record[14..18].copy_from_slice(&length.to_be_bytes());
+        record[25] = if directory { FLAG_DIRECTORY } else { 0 };
+        record[32] = name.len() as u8;
+        record.extend_from_slice(name);
+        if record.len() % 2 == 1 {
+            record.push(0);
+        }
+        record[0] = record.len() as u8;
+        record
+    }
+
+    fn descriptor(kind: u8, root: &[u8], escapes: &[u8]) -> Vec<u8> {
+        let mut descriptor = vec![0; SECTOR_SIZE];
+        descriptor[0] = kind;
+        descriptor[1..6].copy_from_slice(b"CD001");
+        descriptor[6] = 1;
+        descriptor[88..88 + escapes.len()].copy_from_slice(escapes);
+        descriptor[128..130].copy_from_slice(&(SECTOR_SIZE as u16).to_le_bytes());
+        descriptor[ROOT_RECORD_OFFSET..ROOT_RECORD_OFFSET + root.len()].copy_from_slice(root);
+        descriptor
+    }
+
+    fn joliet(name: &str) -> Vec<u8> {
+        name.encode_utf16().flat_map(|c| c.to_be_bytes()).collect()
+    }
+
+    #[test]
+    fn finds_joliet_files() {
+        // Sectors: 16 primary, 17 Joliet, 18 terminator, 19 ISO root, 20 Joliet root,
+        // 21 Joliet subdirectory, 22 and 23 file contents.
+        let sector = SECTOR_SIZE as u32;
+        let mut image = vec![0; 16 * SECTOR_SIZE];
+        image.extend(descriptor(
+            DESCRIPTOR_PRIMARY,
+            &record(&[0], 19, sector, true),
+            b"",
+        ));
+        image.extend(descriptor(
+            DESCRIPTOR_SUPPLEMENTARY,
+            &record(&[0], 20, sector, true),
+            b"%/E",
+        ));
+        image.extend(descriptor(DESCRIPTOR_TERMINATOR, b"", b""));
+
+        let mut directory = |records: Vec<Vec<u8>>| {
+            let mut sector = records.concat();
+            sector.resize(SECTOR_SIZE, 0);
+            image.extend(sector);
+        };
+        directory(vec![
+            record(&[0], 19, sector, true),
+            record(&[1], 19, sector, true),
+            record(b"TMPLS.3CN;1", 22, 5, false),
+        ]);
+        directory(vec![
+            record(&[0], 20, sector, true),
+            record(&[1], 20, sector, true),
+            record(&joliet("3D Movie Maker"), 21, sector, true),
+            record(&joliet("readme.txt;1"), 23, 6, false),
+        ]);
+        directory(vec![
+            record(&[0], 21, sector, true),
+            record(&[1], 20, sector, true),
+            record(&joliet("Templates.3cn;1"), 22, 5, false),
+        ]);
+        let mut contents = b"chunkreadme!".to_vec();
+        contents.resize(2 * SECTOR_SIZE, 0);
+        contents.copy_within(5..11, SECTOR_SIZE);
+        image.extend(contents);
+
+        let iso = IsoImage::open(&image).unwrap();
+        assert!(iso.joliet);
+        let listing = iso.files();
+        assert!(listing.errors.is_empty());
+        let paths: Vec<_> = listing.files.into_iter().map(|f| f.path).collect();
+        assert_eq!(paths, vec!["readme.txt", "3D Movie Maker/Templates.3cn"]);
+
+        let chunky = iso.chunky_files().files;
+        assert_eq!(chunky.len(), 1);
+        assert_eq!(chunky[0].data, b"chunk");
+        let readme = iso.find("/README.TXT").unwrap().unwrap();
+        assert_eq!(readme.data, b"readme");
+    }
+
+    #[test]
+    fn skips_damaged_records() {
+        // Sectors: 16 primary, 17 terminator, 18 root, 19 file contents.
+        let sector = SECTOR_SIZE as u32;
+        let mut image = vec![0; 16 * SECTOR_SIZE];
+        image.extend(descriptor(
+            DESCRIPTOR_PRIMARY,
+            &record(&[0], 18, sector, true),
+            b"",
+        ));
+        image.extend(descriptor(DESCRIPTOR_TERMINATOR, b"", b""));
+
+        // A record too short for its name, a directory and a file past the end of the image, and
+        // two good files around them.
+        let mut short = record(b"SHORT.3CN;1", 19, 5, false);
+        short[32] = 40;
+        let mut root = [
+            record(&[0], 18, sector, true),
+            record(&[1], 18, sector, true),
+            record(b"A.3CN;1", 19, 5, false),
+            short,
+            record(b"LOST", 99, sector, true),
+            record(b"LOST.3TH;1", 99, 5, false),
+            record(b"Z.3CN;1", 19, 5, false),
+        ]
+        .concat();
+        root.resize(SECTOR_SIZE, 0);
+        image.extend(root);
+        let mut contents = b"chunk".to_vec();
+        contents.resize(SECTOR_SIZE, 0);
+        image.extend(contents);
+
+        let iso = IsoImage::open(&image).unwrap();
+        let listing = iso.chunky_files();
+        let paths: Vec<_> = listing.files.iter().map(|f| &f.path[..]).collect();
+        assert_eq!(paths, vec!["A.3CN", "Z.3CN"]);
+        let errors: Vec<_> = listing.errors.iter().map(|(p, _)| &p[..]).collect();
+        assert_eq!(errors, vec!["", "LOST.3TH", "LOST"]);
+
+        assert_eq!(iso.find("z.3cn").unwrap().unwrap().data, b"chunk");
+        assert!(iso.find("LOST/TMPLS.3CN").is_err());
+    }
+}
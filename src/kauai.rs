@@ -1,29 +1,310 @@
 use std::borrow::Cow;
 
-use anyhow::{bail, Result};
+use bitvec::prelude::*;
+use byteorder::{BigEndian, ReadBytesExt};
 
+use crate::error::{Error, ErrorKind, Result};
+
+mod bits;
 mod kcd2;
 mod kcdc;
+mod lz;
+mod stream;
+
+pub use stream::Decoder;
+
+/// The signature of a file packed with a Kauai codec.
+pub const SIGNATURE_PACKED: &[u8] = b"apak";
+/// The signature of a file stored as is, in the same wrapper as a packed one.
+pub const SIGNATURE_STORED: &[u8] = b"puak";
 
 pub fn unpack(input: &[u8]) -> Result<Cow<'_, [u8]>> {
     let Some(packed) = input.get(0..4) else {
-        bail!("Too short");
+        return Err(Error::truncated("pack signature", input.len()));
     };
 
     Ok(match packed {
-        b"puak" => Cow::Borrowed(&input[4..]),
-        b"apak" => Cow::Owned(decode(&input[4..])?),
-        _ => bail!("Unsupported signature {}", packed.escape_ascii()),
+        SIGNATURE_STORED => Cow::Borrowed(&input[4..]),
+        SIGNATURE_PACKED => Cow::Owned(decode(&input[4..]).map_err(|e| e.offset_by(4))?),
+        _ => {
+            return Err(Error::unsupported(
+                format!("signature {}", packed.escape_ascii()),
+                0,
+            ))
+        }
+    })
+}
+
+/// Wraps `input` so [`unpack`] can read it: packed with the named codec, or stored as is if
+/// there's no codec.
+pub fn pack(codec: Option<&[u8]>, input: &[u8], level: Level) -> Result<Vec<u8>> {
+    Ok(match codec {
+        Some(codec) => [SIGNATURE_PACKED, &encode_with_level(codec, input, level)?].concat(),
+        None => [SIGNATURE_STORED, input].concat(),
     })
 }
 
 pub fn decode(input: &[u8]) -> Result<Vec<u8>> {
+    decode_using(input, kcdc::decode, kcd2::decode)
+}
+
+/// Where and why a damaged stream stopped decoding.
+#[derive(Debug, thiserror::Error)]
+#[error("{error}, in the token at bit {bit}")]
+pub struct Damage {
+    /// Where the token that couldn't be decoded starts, in bits from the start of the codec.
+    pub bit: usize,
+    pub error: Error,
+}
+
+/// Decodes as much of `input` as it can, for salvaging damaged data. Returns everything up to the
+/// first token that couldn't be decoded, and what was wrong with it.
+pub fn decode_lenient(input: &[u8]) -> (Vec<u8>, Option<Damage>) {
+    let mut decoder = match Decoder::new(input) {
+        Ok(decoder) => decoder.with_exact_end(),
+        Err(error) => {
+            let bit = error.offset * 8;
+            return (Vec::new(), Some(Damage { bit, error }));
+        }
+    };
+    let mut output = Vec::new();
+    let damage = decoder.read_all(&mut output).err();
+    (output, damage)
+}
+
+/// Decodes with the original bit at a time decoders, which [`decode`] is checked and benchmarked
+/// against. They're only built for tests and with the `bench` feature.
+#[cfg(any(test, feature = "bench"))]
+pub fn decode_bitwise(input: &[u8]) -> Result<Vec<u8>> {
+    decode_using(input, kcdc::decode_bitwise, kcd2::decode_bitwise)
+}
+
+type DecodeFn = fn(&[u8]) -> Result<Vec<u8>>;
+
+fn decode_using(input: &[u8], kcdc: DecodeFn, kcd2: DecodeFn) -> Result<Vec<u8>> {
     let Some(codec) = input.get(..4) else {
-        bail!("Too short");
+        return Err(Error::truncated("codec", input.len()));
     };
     Ok(match codec {
-        b"KCDC" => kcdc::decode(&input[4..])?,
-        b"KCD2" => kcd2::decode(&input[4..])?,
-        _ => bail!("Unsupported codec {}", codec.escape_ascii()),
+        b"KCDC" => kcdc(&input[4..]).map_err(|e| e.offset_by(4))?,
+        b"KCD2" => kcd2(&input[4..]).map_err(|e| e.offset_by(4))?,
+        _ => {
+            return Err(Error::new(
+                ErrorKind::UnsupportedCodec(codec.try_into().unwrap()),
+                0,
+            ))
+        }
     })
 }
+
+/// Reads the unpacked length both codecs start with, returning it and a reader for the bitstream
+/// after it.
+fn read_header(mut input: &[u8]) -> Result<(usize, bits::BitReader<'_>)> {
+    let Ok(len) = input.read_u32::<BigEndian>() else {
+        return Err(Error::truncated("packed length", input.len()));
+    };
+    let Some(input) = input.get(1..) else {
+        return Err(Error::truncated("packed data", 4));
+    };
+    Ok((len as usize, bits::BitReader::new(input)))
+}
+
+/// Handles a stop code after `decoded` bytes, for the decoders' `decode_token` to return. With
+/// `exact_end`, a stream that stops before it has produced the unpacked length from its header is
+/// damaged.
+fn stop(decoded: usize, len: usize, exact_end: bool, position: usize) -> Result<bool> {
+    if exact_end && decoded != len {
+        return Err(Error::invalid(
+            format!("Stopped after {decoded} of {len} bytes"),
+            position,
+        ));
+    }
+    Ok(false)
+}
+
+/// Packs `input` with the named codec at the default level, producing data that [`decode`]
+/// accepts.
+pub fn encode(codec: &[u8], input: &[u8]) -> Result<Vec<u8>> {
+    encode_with_level(codec, input, Level::default())
+}
+
+/// Packs `input` with the named codec, searching for repeated data as hard as `level` asks.
+pub fn encode_with_level(codec: &[u8], input: &[u8], level: Level) -> Result<Vec<u8>> {
+    let Ok(length) = u32::try_from(input.len()) else {
+        return Err(Error::unsupported("chunk size", input.len()));
+    };
+    let mut output = Vec::with_capacity(input.len() + input.len() / 8 + 16);
+    output.extend_from_slice(codec);
+    output.extend_from_slice(&length.to_be_bytes());
+    output.push(0);
+    let bits = match codec {
+        b"KCDC" => kcdc::encode(input, level),
+        b"KCD2" => kcd2::encode(input, level),
+        _ => {
+            return Err(Error::new(
+                ErrorKind::UnsupportedCodec(codec.try_into().unwrap_or(*b"????")),
+                0,
+            ))
+        }
+    };
+    output.extend_from_slice(&finish(bits));
+    Ok(output)
+}
+
+/// How hard the encoders search for repeated data, from 0, which writes everything as literals,
+/// to 9. Each level compares twice as many earlier positions as the one before, and levels from 4
+/// up also check whether waiting a byte finds a better match.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Level(u8);
+
+impl Level {
+    pub const MAX: Level = Level(9);
+
+    pub fn new(level: u8) -> Option<Level> {
+        (level <= Self::MAX.0).then_some(Level(level))
+    }
+
+    fn max_chain(self) -> usize {
+        if self.0 == 0 {
+            0
+        } else {
+            1 << self.0
+        }
+    }
+
+    fn lazy(self) -> bool {
+        self.0 >= 4
+    }
+}
+
+impl Default for Level {
+    fn default() -> Self {
+        Level(6)
+    }
+}
+
+/// The largest offset a back reference can have. KCDC uses it as its stop code instead.
+const MAX_OFFSET: usize = 0x1241 + (1 << 20) - 1;
+
+/// The longest back reference a length code can describe in every offset class.
+const MAX_LENGTH: usize = 1 << 12;
+
+/// One of the four sizes of back reference offset, which both codecs share.
+struct OffsetClass {
+    /// The bits after the back reference marker that select the class, in stream order.
+    prefix: u32,
+    prefix_length: usize,
+    offset_length: usize,
+    /// The smallest offset in the class, which is written as zero.
+    base: usize,
+    /// Added to the length code, since short matches far back aren't worth writing.
+    length_offset: usize,
+}
+
+const OFFSET_CLASSES: [OffsetClass; 4] = [
+    OffsetClass {
+        prefix: 0b0,
+        prefix_length: 1,
+        offset_length: 6,
+        base: 0x0001,
+        length_offset: 1,
+    },
+    OffsetClass {
+        prefix: 0b01,
+        prefix_length: 2,
+        offset_length: 9,
+        base: 0x0041,
+        length_offset: 1,
+    },
+    OffsetClass {
+        prefix: 0b011,
+        prefix_length: 3,
+        offset_length: 12,
+        base: 0x0241,
+        length_offset: 1,
+    },
+    OffsetClass {
+        prefix: 0b111,
+        prefix_length: 3,
+        offset_length: 20,
+        base: 0x1241,
+        length_offset: 2,
+    },
+];
+
+impl OffsetClass {
+    fn of(offset: usize) -> Option<&'static OffsetClass> {
+        OFFSET_CLASSES
+            .iter()
+            .find(|c| (c.base..c.base + (1 << c.offset_length)).contains(&offset))
+    }
+
+    /// The bits the class prefix and offset take.
+    fn bits(&self) -> usize {
+        self.prefix_length + self.offset_length
+    }
+
+    /// Reads a class prefix and offset, returning the class and the offset.
+    fn read(reader: &mut bits::BitReader) -> Option<(&'static OffsetClass, usize)> {
+        let class = &OFFSET_CLASSES[reader.leading_ones(3) as usize];
+        reader.bits(class.prefix_length as u32)?;
+        let offset = reader.bits(class.offset_length as u32)? as usize + class.base;
+        Some((class, offset))
+    }
+
+    fn write(&self, output: &mut BitVec<u8, Lsb0>, offset: usize) {
+        push_bits(output, self.prefix, self.prefix_length);
+        push_bits(output, (offset - self.base) as u32, self.offset_length);
+    }
+}
+
+/// The bits [`write_length`] takes for `length`, or `None` if it can't be written.
+fn length_bits(length: usize) -> Option<usize> {
+    if !(1..1 << 12).contains(&length) {
+        return None;
+    }
+    Some(2 * (usize::BITS - 1 - length.leading_zeros()) as usize + 1)
+}
+
+/// Writes a length of 1 to 4095 as a run of ones giving its bit length, a zero, and the bits
+/// below its highest.
+fn write_length(output: &mut BitVec<u8, Lsb0>, length: usize) {
+    let bits = usize::BITS - 1 - length.leading_zeros();
+    push_bits(output, (1 << bits) - 1, bits as usize + 1);
+    push_bits(output, (length - (1 << bits)) as u32, bits as usize);
+}
+
+/// Appends the low `count` bits of `value` to a bitstream.
+fn push_bits(output: &mut BitVec<u8, Lsb0>, value: u32, count: usize) {
+    output.extend_from_bitslice(&value.view_bits::<Lsb0>()[..count]);
+}
+
+/// Fills out the last byte of a bitstream with ones, which no decoder reads past a stop code.
+fn finish(mut bits: BitVec<u8, Lsb0>) -> Vec<u8> {
+    while !bits.len().is_multiple_of(8) {
+        bits.push(true);
+    }
+    bits.into_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pack_round_trip() {
+        let input = b"a resource, a resource, a resource";
+        for codec in [Some(&b"KCDC"[..]), Some(b"KCD2"), None] {
+            let packed = pack(codec, input, Level::default()).unwrap();
+            let signature = if codec.is_some() {
+                SIGNATURE_PACKED
+            } else {
+                SIGNATURE_STORED
+            };
+            assert_eq!(&packed[..4], signature);
+            assert_eq!(&unpack(&packed).unwrap()[..], input);
+        }
+        assert!(pack(Some(b"KCD3"), input, Level::default()).is_err());
+        assert!(unpack(b"KCDC").unwrap_err().is_unsupported());
+    }
+}
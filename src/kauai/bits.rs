@@ -0,0 +1,188 @@
+pub enum Length {
+    Ok(usize),
+    Break,
+    /// Ones up to the end of the input, which Kauai's decoder also takes as a stop code.
+    End,
+}
+
+/// Reads a least significant bit first bitstream through a 64 bit buffer, which is refilled
+/// several bytes at a time.
+pub struct BitReader<'a> {
+    input: &'a [u8],
+    /// The next byte of `input` to load into the buffer.
+    next: usize,
+    /// Bits loaded but not yet read, the next one lowest.
+    buffer: u64,
+    /// How many bits of `buffer` are loaded.
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(input: &'a [u8]) -> Self {
+        BitReader {
+            input,
+            next: 0,
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    /// How many whole bytes have been read.
+    pub fn position(&self) -> usize {
+        self.bit_position() / 8
+    }
+
+    /// How many bits have been read.
+    pub fn bit_position(&self) -> usize {
+        self.next * 8 - self.count as usize
+    }
+
+    /// Loads whole bytes until at least 56 bits are buffered or the input runs out.
+    fn refill(&mut self) {
+        if let Some(word) = self.input.get(self.next..self.next + 8) {
+            let word = u64::from_le_bytes(word.try_into().unwrap());
+            self.buffer |= word << self.count;
+            let bytes = (63 - self.count) / 8;
+            self.next += bytes as usize;
+            self.count += bytes * 8;
+        } else {
+            while self.count <= 56 && self.next < self.input.len() {
+                self.buffer |= (self.input[self.next] as u64) << self.count;
+                self.next += 1;
+                self.count += 8;
+            }
+        }
+    }
+
+    /// Makes at least `count` bits available, if the input has that many left.
+    fn ensure(&mut self, count: u32) -> bool {
+        if self.count < count {
+            self.refill();
+        }
+        self.count >= count
+    }
+
+    fn consume(&mut self, count: u32) {
+        self.buffer = self.buffer.checked_shr(count).unwrap_or(0);
+        self.count -= count;
+    }
+
+    /// Reads `count` bits, up to 32, as a little endian number.
+    pub fn bits(&mut self, count: u32) -> Option<u32> {
+        if !self.ensure(count) {
+            return None;
+        }
+        let value = (self.buffer & ((1 << count) - 1)) as u32;
+        self.consume(count);
+        Some(value)
+    }
+
+    /// Looks at the next nine bits and, if they're a zero and a byte, returns the byte without
+    /// reading it.
+    pub fn peek_literal(&mut self) -> Option<u8> {
+        if !self.ensure(9) || self.buffer & 1 != 0 {
+            return None;
+        }
+        Some((self.buffer >> 1) as u8)
+    }
+
+    /// Skips `count` bits that are known to be there.
+    pub fn skip(&mut self, count: u32) {
+        self.consume(count);
+    }
+
+    pub fn bit(&mut self) -> Option<bool> {
+        self.bits(1).map(|bit| bit != 0)
+    }
+
+    /// Counts the ones before the next zero, looking no further than `max` bits or the end of the
+    /// input, without reading them.
+    pub fn leading_ones(&mut self, max: u32) -> u32 {
+        self.ensure(max);
+        (!self.buffer).trailing_zeros().min(self.count).min(max)
+    }
+
+    /// Reads a length code written by [`write_length`](super::write_length). Twelve ones, or ones
+    /// up to the end of the input, can't start one.
+    pub fn length(&mut self) -> Option<Length> {
+        let ones = self.leading_ones(12);
+        if ones == 12 {
+            return Some(Length::Break);
+        }
+        if ones == self.count {
+            return Some(Length::End);
+        }
+        self.consume(ones + 1);
+        Some(Length::Ok(self.bits(ones)? as usize + (1 << ones)))
+    }
+
+    /// Appends `count` bytes that are stored from the next byte boundary, after the low bits of
+    /// the last byte, and followed by its high bits. Returns `None`, reading nothing, if the input
+    /// doesn't hold them all.
+    pub fn read_run(&mut self, count: usize, output: &mut Vec<u8>) -> Option<()> {
+        let position = self.bit_position();
+        if (self.input.len() * 8 - position) / 8 < count {
+            return None;
+        }
+        let head = position.wrapping_neg() as u32 % 8;
+        let low = self.bits(head)?;
+        // The buffer now starts on a byte boundary, so the body can be copied straight from the
+        // input once the buffer is dropped.
+        let start = self.position();
+        let body = self.input.get(start..start + count - 1)?;
+        output.extend_from_slice(body);
+        self.next = start + count - 1;
+        self.buffer = 0;
+        self.count = 0;
+        let high = self.bits(8 - head)?;
+        output.push((low | high << head) as u8);
+        Some(())
+    }
+}
+
+/// Appends `length` bytes copied from `offset` bytes back in `output`, which the copy may overlap.
+///
+/// The output from the source on repeats every `offset` bytes, so each step can copy everything
+/// written since the source, doubling the amount copied.
+pub fn copy_match(output: &mut Vec<u8>, offset: usize, length: usize) {
+    let source = output.len() - offset;
+    let end = output.len() + length;
+    output.reserve(length);
+    while output.len() < end {
+        let count = (end - output.len()).min(output.len() - source);
+        output.extend_from_within(source..source + count);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_bits_and_runs() {
+        let input: Vec<u8> = (0..40u8)
+            .map(|i| i.wrapping_mul(37).wrapping_add(11))
+            .collect();
+        let mut reader = BitReader::new(&input);
+        assert_eq!(reader.bits(4), Some(0xb));
+        assert_eq!(reader.bits(12), Some(0x300));
+        assert_eq!(reader.leading_ones(12), 1);
+        assert_eq!(reader.bits(3), Some(0b101));
+
+        // The last byte's low five bits are before the byte boundary and its high three bits
+        // after the body.
+        let mut output = Vec::new();
+        reader.read_run(30, &mut output).unwrap();
+        assert_eq!(output[..29], input[3..32]);
+        assert_eq!(output[29], input[2] >> 3 | input[32] << 5);
+        assert_eq!(reader.position(), 32);
+        assert_eq!(reader.bits(16), Some(0xba15));
+        assert!(reader.read_run(10, &mut output).is_none());
+
+        let mut output = b"abc".to_vec();
+        copy_match(&mut output, 2, 7);
+        assert_eq!(output, b"abcbcbcbcb");
+        copy_match(&mut output, 10, 3);
+        assert_eq!(output, b"abcbcbcbcbabc");
+    }
+}
@@ -1,41 +1,129 @@
+#[cfg(any(test, feature = "bench"))]
 use std::io::Read;
 
-use anyhow::{bail, ensure, Result};
-use bitvec::{field::BitField, prelude::*};
+#[cfg(any(test, feature = "bench"))]
+use bitvec::field::BitField;
+use bitvec::prelude::*;
+#[cfg(any(test, feature = "bench"))]
 use byteorder::{BigEndian, ReadBytesExt};
 
-pub fn decode(mut input: &[u8]) -> Result<Vec<u8>> {
-    let len = input.read_u32::<BigEndian>()? as usize;
+use super::{
+    bits::{copy_match, BitReader, Length},
+    length_bits,
+    lz::{self, Token},
+    push_bits, read_header, stop, write_length, Level, OffsetClass, MAX_LENGTH, MAX_OFFSET,
+};
+use crate::error::{Error, Result};
+
+pub fn decode(input: &[u8]) -> Result<Vec<u8>> {
+    let (len, mut reader) = read_header(input)?;
+    // Don't trust the length from a damaged stream with a huge allocation.
+    let mut output = Vec::with_capacity(len.min(input.len().saturating_mul(64)));
+    while decode_token(&mut reader, &mut output, 0, len, false)? {}
+    Ok(output)
+}
+
+/// Decodes the next literal run or back reference onto `output`, returning false at the stop
+/// code. `output` holds the data decoded so far, less the first `dropped` bytes, and `len` is the
+/// unpacked length from the header. With `exact_end`, the stream must stop with a stop code right
+/// after `len` bytes, rather than by running out.
+pub fn decode_token(
+    reader: &mut BitReader,
+    output: &mut Vec<u8>,
+    dropped: usize,
+    len: usize,
+    exact_end: bool,
+) -> Result<bool> {
+    // Where the bitstream is up to, in bytes from the start of the packed data.
+    let position = |reader: &BitReader| 5 + reader.position();
+    let destination = dropped + output.len();
+
+    let length = match reader.length() {
+        Some(Length::Ok(length)) => length,
+        Some(Length::Break) => return stop(destination, len, exact_end, position(reader)),
+        Some(Length::End) if !exact_end => return Ok(false),
+        Some(Length::End) | None => return Err(Error::truncated("packed data", position(reader))),
+    };
+
+    let Some(bit) = reader.bit() else {
+        return Err(Error::truncated("packed data", position(reader)));
+    };
+
+    if !bit {
+        if destination + length > len {
+            return Err(Error::invalid("Overflow", position(reader)));
+        }
+        if reader.read_run(length, output).is_none() {
+            return Err(Error::truncated("literal run", position(reader)));
+        }
+        return Ok(true);
+    }
+
+    let Some((class, offset)) = OffsetClass::read(reader) else {
+        return Err(Error::invalid("Invalid backref", position(reader)));
+    };
+    let length = length + class.length_offset;
+
+    if offset > output.len() {
+        return Err(Error::invalid(
+            format!("Offset out of range ({offset} > {destination})"),
+            position(reader),
+        ));
+    }
+    if destination + length > len {
+        return Err(Error::invalid(
+            format!("Overflow ({destination} + {length} > {len})"),
+            position(reader),
+        ));
+    }
+    copy_match(output, offset, length);
+    Ok(true)
+}
+
+/// Decodes a bit at a time, as [`decode`] did before it read whole words.
+#[cfg(any(test, feature = "bench"))]
+pub fn decode_bitwise(mut input: &[u8]) -> Result<Vec<u8>> {
+    let Ok(len) = input.read_u32::<BigEndian>() else {
+        return Err(Error::truncated("packed length", input.len()));
+    };
+    let len = len as usize;
     let Some(input) = input.get(1..) else {
-        bail!("Empty encoded data");
+        return Err(Error::truncated("packed data", 4));
     };
-    let mut input = input.view_bits::<Lsb0>();
-    let mut output = Vec::with_capacity(len);
+    let bits = input.view_bits::<Lsb0>();
+    let mut input = bits;
+    // Where the bitstream is up to, in bytes from the start of the packed data.
+    let position = |input: &BitSlice<u8>| 5 + (bits.len() - input.len()) / 8;
+    // Don't trust the length from a damaged stream with a huge allocation.
+    let mut output = Vec::with_capacity(len.min(input.len().saturating_mul(64)));
 
     loop {
         let length = match read_length(&mut input) {
             Some(Length::Ok(length)) => length,
-            Some(Length::Break) => break,
-            None => bail!("Unexpected EOF"),
+            Some(Length::Break | Length::End) => break,
+            None => return Err(Error::truncated("packed data", position(input))),
         };
 
         let Some((bit, rest)) = input.split_first() else {
-            bail!("Unexpected EOF");
+            return Err(Error::truncated("packed data", position(input)));
         };
         input = rest;
 
         let destination = output.len();
         if !bit {
-            ensure!(destination + length <= len, "Overflow");
+            if destination + length > len {
+                return Err(Error::invalid("Overflow", position(input)));
+            }
             output.reserve(destination + length);
 
             let Some(bits) = input.get(..length * 8) else {
-                bail!("Unexpected EOF");
+                return Err(Error::truncated("literal run", position(input)));
             };
             input = &input[length * 8..];
 
             let (head, mut body, tail) = bits.bit_domain().region().unwrap();
-            body.read_to_end(&mut output)?;
+            body.read_to_end(&mut output)
+                .expect("reading from memory can't fail");
             if !tail.is_empty() || !head.is_empty() {
                 output.push(
                     head.iter()
@@ -48,20 +136,22 @@ pub fn decode(mut input: &[u8]) -> Result<Vec<u8>> {
         }
 
         let Some((offset, length)) = read_offset_length(&mut input, length) else {
-            bail!("Invalid backref");
+            return Err(Error::invalid("Invalid backref", position(input)));
         };
 
-        let Some(source) = output
-            .len()
-            .checked_sub(offset) else {
-                eprintln!("{:02x?}", output);
-            bail!("Offset out of range ({offset} > {})", output.len());
+        let Some(source) = output.len().checked_sub(offset) else {
+            return Err(Error::invalid(
+                format!("Offset out of range ({offset} > {})", output.len()),
+                position(input),
+            ));
         };
         let destination = output.len();
-        ensure!(
-            destination + length <= len,
-            "Overflow ({destination} + {length} > {len})",
-        );
+        if destination + length > len {
+            return Err(Error::invalid(
+                format!("Overflow ({destination} + {length} > {len})"),
+                position(input),
+            ));
+        }
         for i in source..source + length {
             output.push(output[i]);
         }
@@ -70,11 +160,62 @@ pub fn decode(mut input: &[u8]) -> Result<Vec<u8>> {
     Ok(output)
 }
 
-enum Length {
-    Ok(usize),
-    Break,
+/// The longest literal run a length code can describe.
+const MAX_RUN: usize = (1 << 12) - 1;
+/// About what ending a literal run early costs, in the length code and flag of the run after a
+/// back reference.
+const RUN_SPLIT_BITS: isize = 10;
+
+/// The bits a back reference saves over writing its bytes in a literal run, or `None` if it can't
+/// be written.
+fn savings(offset: usize, length: usize) -> Option<isize> {
+    let class = OffsetClass::of(offset)?;
+    let bits = length_bits(length.checked_sub(class.length_offset)?)? + 1 + class.bits();
+    Some((length * 8) as isize - bits as isize - RUN_SPLIT_BITS)
+}
+
+/// Encodes `input` as a bitstream of literal runs and back references ending in a stop code.
+pub fn encode(input: &[u8], level: Level) -> BitVec<u8, Lsb0> {
+    let mut output = BitVec::with_capacity(input.len() * 8 + input.len() / 128 + 32);
+    let mut position = 0;
+    let mut run_start = 0;
+    for token in lz::parse(input, level, MAX_OFFSET, MAX_LENGTH, savings) {
+        match token {
+            Token::Literal => position += 1,
+            Token::Match(m) => {
+                write_run(&mut output, &input[run_start..position]);
+                let class = OffsetClass::of(m.offset).unwrap();
+                write_length(&mut output, m.length - class.length_offset);
+                output.push(true);
+                class.write(&mut output, m.offset);
+                position += m.length;
+                run_start = position;
+            }
+        }
+    }
+    write_run(&mut output, &input[run_start..]);
+    // Twelve ones can't start a length, so they stop the stream.
+    push_bits(&mut output, 0xfff, 12);
+    output
+}
+
+/// Writes `literals` as as many runs as their length needs.
+fn write_run(output: &mut BitVec<u8, Lsb0>, literals: &[u8]) {
+    for run in literals.chunks(MAX_RUN) {
+        write_length(output, run.len());
+        output.push(false);
+
+        // Runs are copied as whole bytes wherever the stream is byte aligned. The bits before the
+        // first aligned byte and after the last one together hold the run's final byte.
+        let (last, body) = run.split_last().unwrap();
+        let head = (8 - output.len() % 8) % 8;
+        push_bits(output, *last as u32, head);
+        output.extend_from_raw_slice(body);
+        push_bits(output, (*last >> head) as u32, 8 - head);
+    }
 }
 
+#[cfg(any(test, feature = "bench"))]
 fn read_length<T, O>(input: &mut &BitSlice<T, O>) -> Option<Length>
 where
     T: BitStore,
@@ -97,6 +238,7 @@ where
     }))
 }
 
+#[cfg(any(test, feature = "bench"))]
 fn read_offset_length<T, O>(
     input: &mut &BitSlice<T, O>,
     base_length: usize,
@@ -124,3 +266,51 @@ where
 
     Some((offset, base_length + length_offset))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kauai;
+
+    #[test]
+    fn encode_round_trip() {
+        // Pseudo-random bytes that repeat at distances in each offset class.
+        let mut state = 7u32;
+        let mut noise = |len: usize| -> Vec<u8> {
+            (0..len)
+                .map(|_| {
+                    state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                    (state >> 16) as u8
+                })
+                .collect()
+        };
+        let mut input = noise(0x30);
+        for distance in [0x30, 0x200, 0x1000, 0x20000] {
+            let repeat = input[input.len() - 0x30..].to_vec();
+            input.extend(noise(distance - 0x30));
+            input.extend(repeat);
+        }
+        input.extend([0; 10_000]);
+        input.extend(noise(MAX_RUN + 10));
+
+        let mut sizes = Vec::new();
+        for level in [0, 1, 6, 9] {
+            let level = Level::new(level).unwrap();
+            let packed = kauai::encode_with_level(b"KCD2", &input, level).unwrap();
+            assert_eq!(kauai::decode(&packed).unwrap(), input);
+            assert_eq!(kauai::decode_bitwise(&packed).unwrap(), input);
+            sizes.push(packed.len());
+        }
+        // Level 0 writes only literal runs; the others find the repeats and the zeros.
+        assert!(sizes[0] > input.len());
+        assert!(sizes[1..].iter().all(|&size| size < input.len() - 10_000));
+        assert!(sizes[3] <= sizes[1]);
+
+        for input in [&b""[..], b"a", b"aa", b"aaa", b"abab"] {
+            assert_eq!(
+                kauai::decode(&kauai::encode(b"KCD2", input).unwrap()).unwrap(),
+                input
+            );
+        }
+    }
+}
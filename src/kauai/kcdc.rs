@@ -1,27 +1,134 @@
-use anyhow::{bail, ensure, Result};
-use bitvec::{field::BitField, prelude::*};
+#[cfg(any(test, feature = "bench"))]
+use bitvec::field::BitField;
+use bitvec::prelude::*;
+#[cfg(any(test, feature = "bench"))]
 use byteorder::{BigEndian, ReadBytesExt};
 
-const OFFSET_STOP: usize = 0x101240;
+use super::{
+    bits::{copy_match, BitReader, Length},
+    length_bits,
+    lz::{self, Token},
+    push_bits, read_header, stop, write_length, Level, OffsetClass, MAX_LENGTH, MAX_OFFSET,
+};
+use crate::error::{Error, Result};
 
-pub fn decode(mut input: &[u8]) -> Result<Vec<u8>> {
-    let len = input.read_u32::<BigEndian>()? as usize;
+const OFFSET_STOP: usize = MAX_OFFSET;
+
+pub fn decode(input: &[u8]) -> Result<Vec<u8>> {
+    let (len, mut reader) = read_header(input)?;
+    // Don't trust the length from a damaged stream with a huge allocation.
+    let mut output = Vec::with_capacity(len.min(input.len().saturating_mul(64)));
+    while decode_token(&mut reader, &mut output, 0, len, false)? {}
+    Ok(output)
+}
+
+/// Decodes the next back reference, or up to [`MAX_LENGTH`] byte literals, onto `output`,
+/// returning false at the stop code. `output` holds the data decoded so far, less the first
+/// `dropped` bytes, and `len` is the unpacked length from the header. With `exact_end`, the stream
+/// must stop right after `len` bytes.
+pub fn decode_token(
+    reader: &mut BitReader,
+    output: &mut Vec<u8>,
+    dropped: usize,
+    len: usize,
+    exact_end: bool,
+) -> Result<bool> {
+    // Where the bitstream is up to, in bytes from the start of the packed data.
+    let position = |reader: &BitReader| 5 + reader.position();
+
+    // Byte literals are a zero and eight bits, so read them in one go where possible.
+    let start = output.len();
+    while let Some(byte) = reader.peek_literal() {
+        if dropped + output.len() >= len {
+            // Finish with the literals before, so the error is about this one alone.
+            if output.len() > start {
+                return Ok(true);
+            }
+            return Err(Error::invalid("Overflow", position(reader)));
+        }
+        reader.skip(9);
+        output.push(byte);
+        if output.len() - start == MAX_LENGTH {
+            return Ok(true);
+        }
+    }
+    if output.len() > start {
+        return Ok(true);
+    }
+    let destination = dropped + output.len();
+    let Some(bit) = reader.bit() else {
+        return Err(Error::truncated("packed data", position(reader)));
+    };
+
+    if !bit {
+        return Err(Error::truncated("byte literal", position(reader)));
+    }
+
+    let Some((offset, length)) = read_match(reader) else {
+        return Err(Error::invalid("Invalid backref", position(reader)));
+    };
+
+    if offset == OFFSET_STOP {
+        return stop(destination, len, exact_end, position(reader));
+    }
+
+    if offset > output.len() {
+        return Err(Error::invalid(
+            format!("Offset out of range ({offset} > {destination})"),
+            position(reader),
+        ));
+    }
+    if destination + length > len {
+        return Err(Error::invalid(
+            format!("Overflow ({destination} + {length} > {len})"),
+            position(reader),
+        ));
+    }
+    copy_match(output, offset, length);
+    Ok(true)
+}
+
+/// Reads a back reference's offset and length, or just the offset of a stop code.
+fn read_match(reader: &mut BitReader) -> Option<(usize, usize)> {
+    let (class, offset) = OffsetClass::read(reader)?;
+    if offset == OFFSET_STOP {
+        return Some((offset, 0));
+    }
+    match reader.length()? {
+        Length::Ok(length) => Some((offset, length + class.length_offset)),
+        Length::Break | Length::End => None,
+    }
+}
+
+/// Decodes a bit at a time, as [`decode`] did before it read whole words.
+#[cfg(any(test, feature = "bench"))]
+pub fn decode_bitwise(mut input: &[u8]) -> Result<Vec<u8>> {
+    let Ok(len) = input.read_u32::<BigEndian>() else {
+        return Err(Error::truncated("packed length", input.len()));
+    };
+    let len = len as usize;
     let Some(input) = input.get(1..) else {
-        bail!("Empty encoded data");
+        return Err(Error::truncated("packed data", 4));
     };
-    let mut input = input.view_bits::<Lsb0>();
-    let mut output = Vec::with_capacity(len);
+    let bits = input.view_bits::<Lsb0>();
+    let mut input = bits;
+    // Where the bitstream is up to, in bytes from the start of the packed data.
+    let position = |input: &BitSlice<u8>| 5 + (bits.len() - input.len()) / 8;
+    // Don't trust the length from a damaged stream with a huge allocation.
+    let mut output = Vec::with_capacity(len.min(input.len().saturating_mul(64)));
 
     loop {
         let Some((bit, rest)) = input.split_first() else {
-            bail!("Unexpected EOF");
+            return Err(Error::truncated("packed data", position(input)));
         };
         input = rest;
 
         if !bit {
-            ensure!(output.len() < len, "Overflow");
+            if output.len() >= len {
+                return Err(Error::invalid("Overflow", position(input)));
+            }
             let Some(bits) = input.get(0..8) else {
-                bail!("EOF in byte literal");
+                return Err(Error::truncated("byte literal", position(input)));
             };
             input = &input[8..];
             output.push(bits.load_le::<u8>());
@@ -29,7 +136,7 @@ pub fn decode(mut input: &[u8]) -> Result<Vec<u8>> {
         }
 
         let Some((offset, length)) = read_offset_length(&mut input) else {
-            bail!("Invalid backref");
+            return Err(Error::invalid("Invalid backref", position(input)));
         };
 
         if offset == OFFSET_STOP {
@@ -39,13 +146,18 @@ pub fn decode(mut input: &[u8]) -> Result<Vec<u8>> {
         let Some(source) = output
             .len()
             .checked_sub(offset) else {
-            bail!("Offset out of range ({offset} > {})", output.len());
+            return Err(Error::invalid(
+                format!("Offset out of range ({offset} > {})", output.len()),
+                position(input),
+            ));
         };
         let destination = output.len();
-        ensure!(
-            destination + length <= len,
-            "Overflow ({destination} + {length} > {len})",
-        );
+        if destination + length > len {
+            return Err(Error::invalid(
+                format!("Overflow ({destination} + {length} > {len})"),
+                position(input),
+            ));
+        }
         for i in source..source + length {
             output.push(output[i]);
         }
@@ -54,6 +166,42 @@ pub fn decode(mut input: &[u8]) -> Result<Vec<u8>> {
     Ok(output)
 }
 
+/// The bits a back reference saves over writing its bytes as literals, or `None` if it can't be
+/// written.
+fn savings(offset: usize, length: usize) -> Option<isize> {
+    let class = OffsetClass::of(offset).filter(|_| offset < OFFSET_STOP)?;
+    let bits = 1 + class.bits() + length_bits(length.checked_sub(class.length_offset)?)?;
+    Some((length * 9) as isize - bits as isize)
+}
+
+/// Encodes `input` as a bitstream of byte literals and back references ending in a stop code.
+pub fn encode(input: &[u8], level: Level) -> BitVec<u8, Lsb0> {
+    let mut output = BitVec::with_capacity(input.len() * 9 + 32);
+    let mut position = 0;
+    for token in lz::parse(input, level, OFFSET_STOP - 1, MAX_LENGTH, savings) {
+        match token {
+            Token::Literal => {
+                output.push(false);
+                push_bits(&mut output, input[position] as u32, 8);
+                position += 1;
+            }
+            Token::Match(m) => {
+                let class = OffsetClass::of(m.offset).unwrap();
+                output.push(true);
+                class.write(&mut output, m.offset);
+                write_length(&mut output, m.length - class.length_offset);
+                position += m.length;
+            }
+        }
+    }
+
+    // A back reference with the largest 20 bit offset.
+    push_bits(&mut output, 0b1111, 4);
+    push_bits(&mut output, (OFFSET_STOP - 0x1241) as u32, 20);
+    output
+}
+
+#[cfg(any(test, feature = "bench"))]
 fn read_offset_length<T, O>(input: &mut &BitSlice<T, O>) -> Option<(usize, usize)>
 where
     T: BitStore,
@@ -101,6 +249,41 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::kauai;
+
+    #[test]
+    fn encode_round_trip() {
+        // Pseudo-random bytes that repeat at distances in each offset class.
+        let mut state = 1u32;
+        let mut noise = |len: usize| -> Vec<u8> {
+            (0..len)
+                .map(|_| {
+                    state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                    (state >> 16) as u8
+                })
+                .collect()
+        };
+        let mut input = noise(0x30);
+        for distance in [0x30, 0x200, 0x1000, 0x20000] {
+            let repeat = input[input.len() - 0x30..].to_vec();
+            input.extend(noise(distance - 0x30));
+            input.extend(repeat);
+        }
+        input.extend([0; 10_000]);
+        input.extend(b"ab");
+
+        let packed = kauai::encode(b"KCDC", &input).unwrap();
+        assert_eq!(kauai::decode(&packed).unwrap(), input);
+        assert_eq!(kauai::decode_bitwise(&packed).unwrap(), input);
+        // Only the noise should be written as literals.
+        assert!(packed.len() < (input.len() - 10_000) * 9 / 8);
+        for input in [&b""[..], b"a", b"aa", b"aaa"] {
+            assert_eq!(
+                kauai::decode(&kauai::encode(b"KCDC", input).unwrap()).unwrap(),
+                input
+            );
+        }
+    }
 
     #[test]
     #[allow(clippy::unusual_byte_groupings)]
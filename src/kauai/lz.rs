@@ -0,0 +1,201 @@
+use super::Level;
+
+/// Marks an empty slot in the match tables.
+const NONE: u32 = u32::MAX;
+const HASH_BITS: u32 = 16;
+
+/// A repeat of earlier data that an encoder can refer back to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Match {
+    pub offset: usize,
+    pub length: usize,
+    /// How many bits the match saves over writing the same bytes as literals.
+    pub savings: isize,
+}
+
+/// Finds earlier occurrences of the data at a position, using hash chains over three byte
+/// prefixes, plus the latest occurrence of each pair of bytes for short matches close by.
+pub struct MatchFinder<'a> {
+    input: &'a [u8],
+    /// The latest position whose first three bytes have each hash.
+    head: Vec<u32>,
+    /// The previous position with the same hash as each position.
+    prev: Vec<u32>,
+    /// The latest position of each pair of bytes.
+    pairs: Vec<u32>,
+    /// Positions before this one have been added to the tables.
+    next: usize,
+    /// How many earlier positions to compare before settling for the best match so far.
+    max_chain: usize,
+}
+
+impl<'a> MatchFinder<'a> {
+    pub fn new(input: &'a [u8], max_chain: usize) -> Self {
+        MatchFinder {
+            input,
+            head: vec![NONE; 1 << HASH_BITS],
+            prev: vec![NONE; input.len()],
+            pairs: vec![NONE; 1 << 16],
+            next: 0,
+            max_chain,
+        }
+    }
+
+    fn hash(&self, position: usize) -> usize {
+        let bytes = &self.input[position..position + 3];
+        let value = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]);
+        (value.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn pair(&self, position: usize) -> usize {
+        u16::from_le_bytes([self.input[position], self.input[position + 1]]) as usize
+    }
+
+    /// Adds every position before `end` to the tables.
+    fn insert_to(&mut self, end: usize) {
+        while self.next < end {
+            let position = self.next;
+            if position + 3 <= self.input.len() {
+                let hash = self.hash(position);
+                self.prev[position] = self.head[hash];
+                self.head[hash] = position as u32;
+            }
+            if position + 2 <= self.input.len() {
+                let pair = self.pair(position);
+                self.pairs[pair] = position as u32;
+            }
+            self.next += 1;
+        }
+    }
+
+    /// The match for the data at `position` that saves the most bits, given the longest offset
+    /// and length the codec can write, and a function giving the bits a match of an offset and
+    /// length saves, or `None` if the codec can't write it. Positions must be passed in
+    /// increasing order.
+    pub fn find<F>(
+        &mut self,
+        position: usize,
+        max_offset: usize,
+        max_length: usize,
+        savings: F,
+    ) -> Option<Match>
+    where
+        F: Fn(usize, usize) -> Option<isize>,
+    {
+        self.insert_to(position);
+        let input = self.input;
+        let max_length = max_length.min(input.len() - position);
+        if max_length < 2 {
+            return None;
+        }
+
+        let mut best: Option<Match> = None;
+        // Returns true once there's no point looking for a longer match.
+        let mut consider = |candidate: usize| {
+            let offset = position - candidate;
+            if offset > max_offset {
+                return false;
+            }
+            let length = input[candidate..]
+                .iter()
+                .zip(&input[position..position + max_length])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if length < 2 {
+                return false;
+            }
+            let Some(savings) = savings(offset, length) else {
+                return false;
+            };
+            if savings > best.map_or(0, |b| b.savings) {
+                best = Some(Match {
+                    offset,
+                    length,
+                    savings,
+                });
+            }
+            length == max_length
+        };
+
+        let pair = self.pairs[self.pair(position)];
+        if pair != NONE && consider(pair as usize) {
+            return best;
+        }
+        if position + 3 <= input.len() {
+            let mut candidate = self.head[self.hash(position)];
+            for _ in 0..self.max_chain {
+                if candidate == NONE || position - candidate as usize > max_offset {
+                    break;
+                }
+                if consider(candidate as usize) {
+                    break;
+                }
+                candidate = self.prev[candidate as usize];
+            }
+        }
+        best
+    }
+}
+
+/// A piece of the data as an encoder writes it.
+pub enum Token {
+    /// The next byte, as is.
+    Literal,
+    Match(Match),
+}
+
+/// Splits `input` into literals and matches, given the longest offset and length the codec can
+/// write and a function giving the bits a match saves, as for [`MatchFinder::find`].
+///
+/// Each position takes the match saving the most bits, unless, at higher levels, the match at the
+/// next position saves more, in which case a literal is written first.
+pub fn parse<F>(
+    input: &[u8],
+    level: Level,
+    max_offset: usize,
+    max_length: usize,
+    savings: F,
+) -> Vec<Token>
+where
+    F: Fn(usize, usize) -> Option<isize>,
+{
+    let mut tokens = Vec::new();
+    if level.max_chain() == 0 {
+        tokens.resize_with(input.len(), || Token::Literal);
+        return tokens;
+    }
+    let mut finder = MatchFinder::new(input, level.max_chain());
+    let mut find = |position| finder.find(position, max_offset, max_length, &savings);
+
+    let mut position = 0;
+    let mut next = None;
+    while position < input.len() {
+        let current = match next.take() {
+            Some(current) => Some(current),
+            None => find(position),
+        };
+        let current = match current {
+            Some(current) if level.lazy() && position + 1 < input.len() => {
+                match find(position + 1) {
+                    Some(later) if later.savings > current.savings => {
+                        next = Some(later);
+                        None
+                    }
+                    _ => Some(current),
+                }
+            }
+            current => current,
+        };
+        match current {
+            Some(current) => {
+                position += current.length;
+                tokens.push(Token::Match(current));
+            }
+            None => {
+                position += 1;
+                tokens.push(Token::Literal);
+            }
+        }
+    }
+    tokens
+}
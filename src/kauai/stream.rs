@@ -0,0 +1,291 @@
+use std::io::{self, Read};
+
+use super::{bits::BitReader, kcd2, kcdc, read_header, Damage, MAX_OFFSET};
+use crate::error::{Error, ErrorKind, Result};
+
+/// How much decoded data a [`Decoder`] keeps for back references to copy from.
+const WINDOW: usize = MAX_OFFSET;
+
+/// The codec, the unpacked length and the byte after it.
+const HEADER_BITS: usize = 9 * 8;
+
+type DecodeTokenFn = fn(&mut BitReader, &mut Vec<u8>, usize, usize, bool) -> Result<bool>;
+
+/// Unpacks data as it's read, like [`decode`](super::decode) does all at once.
+///
+/// Only the last [`WINDOW`] bytes, as far back as a back reference can reach, are kept, plus up to
+/// as much again before they're dropped, so large chunks can be streamed somewhere else without
+/// holding all of them in memory.
+pub struct Decoder<'a> {
+    reader: BitReader<'a>,
+    decode_token: DecodeTokenFn,
+    len: usize,
+    window: Vec<u8>,
+    /// How many bytes have been dropped from the front of the window.
+    dropped: usize,
+    /// Where the bytes not yet read start in the window.
+    unread: usize,
+    /// Whether a stream that doesn't end with a stop code right after the unpacked length is
+    /// damaged, rather than ending where [`decode`](super::decode) would.
+    exact_end: bool,
+    finished: bool,
+    /// Why the stream stopped early, if it's damaged.
+    damage: Option<Damage>,
+}
+
+impl<'a> Decoder<'a> {
+    /// Starts decoding `input`, which begins with the codec as for [`decode`](super::decode).
+    pub fn new(input: &'a [u8]) -> Result<Self> {
+        let Some(codec) = input.get(..4) else {
+            return Err(Error::truncated("codec", input.len()));
+        };
+        let decode_token: DecodeTokenFn = match codec {
+            b"KCDC" => kcdc::decode_token,
+            b"KCD2" => kcd2::decode_token,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::UnsupportedCodec(codec.try_into().unwrap()),
+                    0,
+                ))
+            }
+        };
+        let (len, reader) = read_header(&input[4..]).map_err(|e| e.offset_by(4))?;
+        Ok(Decoder {
+            reader,
+            decode_token,
+            len,
+            window: Vec::with_capacity(len.min(2 * WINDOW)),
+            dropped: 0,
+            unread: 0,
+            exact_end: false,
+            finished: false,
+            damage: None,
+        })
+    }
+
+    /// Reports a stream that runs out before its stop code, or stops short of the unpacked
+    /// length, as damaged.
+    pub(super) fn with_exact_end(mut self) -> Self {
+        self.exact_end = true;
+        self
+    }
+
+    /// The unpacked length given in the header.
+    pub fn unpacked_len(&self) -> usize {
+        self.len
+    }
+
+    /// Decodes until there are unread bytes in the window or the stream ends. Once the stream
+    /// turns out to be damaged, it ends there, and every later call returns the damage.
+    fn fill(&mut self) -> std::result::Result<(), &Damage> {
+        while self.unread == self.window.len() && !self.finished {
+            if self.window.len() >= 2 * WINDOW {
+                let drop = self.window.len() - WINDOW;
+                self.window.drain(..drop);
+                self.dropped += drop;
+                self.unread -= drop;
+            }
+            let bit = self.reader.bit_position();
+            let decoded = self.window.len();
+            let more = (self.decode_token)(
+                &mut self.reader,
+                &mut self.window,
+                self.dropped,
+                self.len,
+                self.exact_end,
+            );
+            match more {
+                Ok(more) => self.finished = !more,
+                Err(error) => {
+                    // Drop whatever the damaged token managed to write.
+                    self.window.truncate(decoded);
+                    self.finished = true;
+                    self.damage = Some(Damage {
+                        bit: HEADER_BITS + bit,
+                        error: error.offset_by(4),
+                    });
+                }
+            }
+        }
+        match &self.damage {
+            Some(damage) if self.unread == self.window.len() => Err(damage),
+            _ => Ok(()),
+        }
+    }
+
+    /// Appends everything left to `output`, stopping where the stream is damaged, if it is.
+    pub(super) fn read_all(&mut self, output: &mut Vec<u8>) -> std::result::Result<(), Damage> {
+        loop {
+            if self.fill().is_err() {
+                return Err(self.damage.take().unwrap());
+            }
+            if self.unread == self.window.len() {
+                return Ok(());
+            }
+            output.extend_from_slice(&self.window[self.unread..]);
+            self.unread = self.window.len();
+        }
+    }
+}
+
+impl Read for Decoder<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.fill()
+            .map_err(|damage| io::Error::new(io::ErrorKind::InvalidData, damage.to_string()))?;
+        let unread = &self.window[self.unread..];
+        let count = buf.len().min(unread.len());
+        buf[..count].copy_from_slice(&unread[..count]);
+        self.unread += count;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitvec::prelude::*;
+
+    use super::*;
+    use crate::kauai::{
+        self, decode_lenient, finish, kcd2, kcdc, push_bits, write_length, Level, OffsetClass,
+        MAX_LENGTH,
+    };
+
+    #[test]
+    fn streams_past_the_window() {
+        // Written by hand, since encoding a few megabytes takes a while: noise, copied until
+        // there's a window's worth, then copied from as far back as a back reference can reach
+        // until the window has been dropped from twice.
+        let mut bits = BitVec::<u8, Lsb0>::new();
+        let mut state = 5u32;
+        let mut literal = |bits: &mut BitVec<u8, Lsb0>| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            bits.push(false);
+            push_bits(bits, state >> 24, 8);
+        };
+        let back_reference = |bits: &mut BitVec<u8, Lsb0>, offset: usize| {
+            let class = OffsetClass::of(offset).unwrap();
+            bits.push(true);
+            class.write(bits, offset);
+            write_length(bits, MAX_LENGTH - class.length_offset);
+        };
+        let mut len = 0;
+        for _ in 0..0x2000 {
+            literal(&mut bits);
+            len += 1;
+        }
+        while len < 5 * WINDOW {
+            if len % 3 == 0 {
+                literal(&mut bits);
+                len += 1;
+            }
+            back_reference(&mut bits, (MAX_OFFSET - 1).min(len));
+            len += MAX_LENGTH;
+        }
+        push_bits(&mut bits, 0b1111, 4);
+        push_bits(&mut bits, (MAX_OFFSET - 0x1241) as u32, 20);
+
+        let mut packed = b"KCDC".to_vec();
+        packed.extend_from_slice(&(len as u32).to_be_bytes());
+        packed.push(0);
+        packed.extend(finish(bits));
+        let expected = kauai::decode(&packed).unwrap();
+        assert_eq!(expected.len(), len);
+
+        let mut decoder = Decoder::new(&packed).unwrap();
+        assert_eq!(decoder.unpacked_len(), len);
+        // Read in odd sizes, so reads end partway through back references.
+        let mut output = Vec::new();
+        let mut buf = [0; 4099];
+        loop {
+            let count = decoder.read(&mut buf[..1 + output.len() % 4097]).unwrap();
+            if count == 0 {
+                break;
+            }
+            output.extend_from_slice(&buf[..count]);
+        }
+        assert!(output == expected);
+        assert!(decoder.dropped > 0 && decoder.window.len() < 2 * WINDOW + MAX_LENGTH);
+
+        packed.truncate(packed.len() / 2);
+        let mut decoder = Decoder::new(&packed).unwrap();
+        let error = io::copy(&mut decoder, &mut io::sink()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        // Reading again mustn't look like the end of the data.
+        for _ in 0..2 {
+            let error = decoder.read(&mut buf).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn salvages_damaged_streams() {
+        let input = b"the quick brown fox jumps over the lazy dog, then the quick brown fox naps";
+        for codec in [b"KCDC", b"KCD2"] {
+            let packed = kauai::encode(codec, input).unwrap();
+            let (output, damage) = decode_lenient(&packed);
+            assert_eq!(output, input);
+            assert!(damage.is_none());
+
+            // Everything before the token the data ends in is still there.
+            let truncated = &packed[..packed.len() - 4];
+            let (output, damage) = decode_lenient(truncated);
+            let damage = damage.unwrap();
+            assert!(input.starts_with(&output) && output.len() > 40);
+            assert!(damage.bit < truncated.len() * 8);
+            assert!(damage.error.offset <= truncated.len());
+        }
+
+        // A header promising five bytes, then literals for nine.
+        let mut packed = kauai::encode(b"KCDC", b"too many!").unwrap();
+        packed[4..8].copy_from_slice(&5u32.to_be_bytes());
+        let (output, damage) = decode_lenient(&packed);
+        assert_eq!(output, b"too m");
+        let damage = damage.unwrap();
+        assert_eq!(damage.bit, HEADER_BITS + 5 * 9);
+        assert_eq!(damage.error.to_string(), "Overflow at 0xe");
+
+        let (output, damage) = decode_lenient(b"KCD2\0\0");
+        assert!(output.is_empty());
+        assert_eq!(damage.unwrap().bit, 4 * 8);
+    }
+
+    #[test]
+    fn stream_ending_between_tokens_is_damaged() {
+        // Streams cut off right after a token, before their stop codes, which are 24 bits for
+        // KCDC and 12 for KCD2.
+        type EncodeFn = fn(&[u8], Level) -> BitVec<u8, Lsb0>;
+        let codecs: [(&[u8], EncodeFn, usize); 2] =
+            [(b"KCDC", kcdc::encode, 24), (b"KCD2", kcd2::encode, 12)];
+        for (codec, encode, stop_bits) in codecs {
+            let input = b"abcabcabc";
+            let mut bits = encode(input, Level::default());
+            bits.truncate(bits.len() - stop_bits);
+            let mut packed = codec.to_vec();
+            packed.extend_from_slice(&(input.len() as u32).to_be_bytes());
+            packed.push(0);
+            packed.extend(finish(bits));
+
+            let (output, damage) = decode_lenient(&packed);
+            assert_eq!(output, input);
+            assert!(damage.is_some());
+            // Kauai's KCD2 decoder stops at the end of the input, so decode does too.
+            let decoded = kauai::decode(&packed);
+            assert_eq!(decoded.is_ok(), codec == b"KCD2");
+            let mut output = Vec::new();
+            let copied = io::copy(&mut Decoder::new(&packed).unwrap(), &mut output);
+            assert_eq!(copied.is_ok(), decoded.is_ok());
+
+            // A stop code before the unpacked length from the header.
+            let mut packed = kauai::encode(codec, input).unwrap();
+            packed[4..8].copy_from_slice(&10u32.to_be_bytes());
+            assert_eq!(kauai::decode(&packed).unwrap(), input);
+            let (output, damage) = decode_lenient(&packed);
+            assert_eq!(output, input);
+            let damage = damage.unwrap();
+            assert!(damage
+                .error
+                .to_string()
+                .starts_with("Stopped after 9 of 10 bytes"));
+        }
+    }
+}
@@ -0,0 +1,28 @@
+pub mod brender;
+pub mod chunky;
+pub mod diff;
+pub mod error;
+pub mod fsck;
+pub mod ggcl;
+pub mod ggcm;
+pub mod ggf;
+pub mod glbs;
+pub mod glf;
+pub mod glpi;
+pub mod glxf;
+pub mod graph;
+pub mod iso;
+pub mod kauai;
+pub mod library;
+#[allow(dead_code)]
+pub mod mbmp;
+pub mod modl;
+pub mod mtrl;
+pub mod order;
+pub mod query;
+pub mod recover;
+pub mod registry;
+pub mod tmap;
+pub mod tmpl;
+pub mod tree;
+pub mod txxf;
@@ -0,0 +1,168 @@
+use std::{collections::HashSet, ptr};
+
+use crate::{
+    chunky::{ChunkData, ChunkId, ChunkyFile, Forest, IndexEntry},
+    error::{Error, Result},
+    order::Loader,
+    registry::TypedChunk,
+};
+
+/// Several chunky files loaded together, the way 3DMM layers its content files.
+///
+/// A chunk in a file overrides any chunk with the same ID in the files added before it, and
+/// child links are resolved across every file, so an expansion's chunks can refer to the base
+/// game's.
+#[derive(Default)]
+pub struct Library<'a> {
+    files: Vec<ChunkyFile<'a>>,
+}
+
+impl<'a> Library<'a> {
+    pub fn new() -> Self {
+        Library::default()
+    }
+
+    /// Adds a file whose chunks take precedence over those of the files already added.
+    pub fn push(&mut self, file: ChunkyFile<'a>) {
+        self.files.push(file);
+    }
+
+    pub fn files(&self) -> &[ChunkyFile<'a>] {
+        &self.files
+    }
+
+    /// Finds the chunk with the given ID in the latest file that has one.
+    pub fn get(&self, id: &ChunkId) -> Option<&IndexEntry<'a>> {
+        self.files.iter().rev().find_map(|f| f.index.get(id))
+    }
+
+    /// Every chunk that isn't overridden by a later file.
+    pub fn entries(&self) -> impl Iterator<Item = &IndexEntry<'a>> {
+        let mut seen = HashSet::new();
+        self.files
+            .iter()
+            .rev()
+            .flat_map(|f| f.index.values())
+            .filter(move |e| seen.insert(e.id))
+    }
+
+    /// The file an entry from this library was read from.
+    pub fn file_of(&self, entry: &IndexEntry) -> Option<&ChunkyFile<'a>> {
+        self.files
+            .iter()
+            .rev()
+            .find(|f| f.index.get(&entry.id).is_some_and(|e| ptr::eq(e, entry)))
+    }
+
+    pub fn get_child(
+        &self,
+        entry: &IndexEntry,
+        child_id: u32,
+        tag: impl AsRef<[u8]>,
+    ) -> Option<&IndexEntry<'a>> {
+        self.get(entry.get_child(child_id, tag)?)
+    }
+
+    /// Like [`Library::get_child`], but reports a missing child as an error.
+    pub fn get_child_entry(
+        &self,
+        entry: &IndexEntry,
+        child_id: u32,
+        tag: &str,
+    ) -> Result<&IndexEntry<'a>> {
+        self.get_child(entry, child_id, tag)
+            .ok_or_else(|| Error::missing_child(entry.id, tag, child_id))
+    }
+
+    pub fn get_chunk(&self, entry: &IndexEntry) -> Result<ChunkData<'a>> {
+        self.owning_file(entry)?.get_chunk(entry)
+    }
+
+    pub fn forest(&self, entry: &IndexEntry) -> Result<Forest<'a>> {
+        self.owning_file(entry)?.forest(entry)
+    }
+
+    /// Unpacks and parses a chunk.
+    pub fn load_chunk<T>(&self, entry: &IndexEntry) -> Result<T>
+    where
+        T: for<'b> Loader<'b>,
+    {
+        self.owning_file(entry)?.load_chunk(entry)
+    }
+
+    /// Unpacks a chunk and decodes it according to its tag.
+    pub fn decode_chunk(&self, entry: &IndexEntry) -> Result<TypedChunk<'a>> {
+        self.owning_file(entry)?.decode_chunk(entry)
+    }
+
+    fn owning_file(&self, entry: &IndexEntry) -> Result<&ChunkyFile<'a>> {
+        self.file_of(entry)
+            .ok_or_else(|| Error::invalid("Chunk is not in the library", 0).in_chunk(entry.id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunky::{
+            testing::{chunk, file, id, link},
+            ChunkFlags,
+        },
+        error::ErrorKind,
+    };
+
+    #[test]
+    fn later_files_override_earlier_ones() {
+        let base = file([
+            (
+                "MTRL:1",
+                chunk(ChunkFlags::LONER, "", Vec::new(), b"base material"),
+            ),
+            (
+                "TMAP:2",
+                chunk(ChunkFlags::LONER, "", Vec::new(), b"base texture"),
+            ),
+        ]);
+        let expansion = file([
+            (
+                "MTRL:1",
+                chunk(
+                    ChunkFlags::LONER,
+                    "",
+                    vec![link("TMAP:2", 0)],
+                    b"expansion material",
+                ),
+            ),
+            (
+                "MTRL:3",
+                chunk(ChunkFlags::LONER, "", Vec::new(), b"new material"),
+            ),
+        ]);
+
+        let mut library = Library::new();
+        library.push(ChunkyFile::load(&base).unwrap());
+        library.push(ChunkyFile::load(&expansion).unwrap());
+
+        let material = library.get(&id("MTRL:1")).unwrap();
+        assert_eq!(
+            &library.get_chunk(material).unwrap()[..],
+            b"expansion material"
+        );
+        let texture = library.get_child(material, 0, "TMAP").unwrap();
+        assert_eq!(&library.get_chunk(texture).unwrap()[..], b"base texture");
+        let e = library.get_child_entry(material, 1, "TMAP").unwrap_err();
+        assert!(matches!(
+            e.kind,
+            ErrorKind::MissingChild { child_id: 1, .. }
+        ));
+        assert_eq!(e.chunk, Some(id("MTRL:1")));
+        let new_material = library.get(&id("MTRL:3")).unwrap();
+        let e = library.decode_chunk(new_material).err().unwrap();
+        assert_eq!(e.chunk, Some(id("MTRL:3")));
+
+        let mut ids: Vec<_> = library.entries().map(|e| e.id).collect();
+        ids.sort_unstable();
+        assert_eq!(ids, vec![id("MTRL:1"), id("MTRL:3"), id("TMAP:2")]);
+    }
+}
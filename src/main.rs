@@ -5,13 +5,16 @@ use std::{
     io::BufWriter,
     io::Write,
     iter, mem,
-    ops::{BitOr, Sub},
+    ops::{BitOr, Deref, Range, Sub},
+    path::{Path, PathBuf},
 };
 
 use anyhow::{bail, Context, Result};
 use byteorder::{LittleEndian, WriteBytesExt};
 use chunky::{ChunkFlags, ChunkId, ChunkyFile};
+use clap::{Parser, Subcommand, ValueEnum};
 use embedded_graphics_core::prelude::RgbColor;
+use encoding_rs::Encoding;
 use gltf::{
     binary::Header,
     buffer::Target,
@@ -44,29 +47,13 @@ use rectangle_pack::{
 use serde_json::{Number, Value};
 use tinybmp::RawBmp;
 
-use crate::{
-    ggcl::AnimationCells, ggcm::Costumes, glbs::BodyPartSets, glpi::Armature,
-    glxf::AnimationTransforms, modl::Model, tmap::TextureMap, tmpl::Template,
-    txxf::TextureTransform,
+use threedeemm_dump::{
+    chunky, chunky::IndexEntry, diff, fsck, ggcl::AnimationCells, ggcm::Costumes,
+    glbs::BodyPartSets, glpi::Armature, glxf::AnimationTransforms, graph, iso::IsoImage,
+    kauai, kauai::Level, library::Library, modl::Model, mtrl, order, query::Query, recover,
+    tmap::TextureMap, tmpl::Template, tree, txxf, txxf::TextureTransform,
 };
 
-mod brender;
-mod chunky;
-mod ggcl;
-mod ggcm;
-mod ggf;
-mod glbs;
-mod glf;
-mod glpi;
-mod glxf;
-mod kauai;
-mod mbmp;
-mod modl;
-mod mtrl;
-mod order;
-mod tmap;
-mod tmpl;
-mod txxf;
 
 struct TemplateData {
     armature: Armature,
@@ -94,31 +81,663 @@ struct MaterialData {
     texture_transform: Option<txxf::TextureTransform>,
 }
 
+#[derive(Parser)]
+#[command(about = "Dump and convert 3D Movie Maker content files")]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+    /// Windows code page single byte chunk names are in, for files from localized releases.
+    #[arg(long, global = true, default_value = "1252", value_parser = parse_code_page)]
+    code_page: &'static Encoding,
+    /// Only work on the chunks matching a query, such as 'tag=TMPL and name="Willy*"'.
+    /// Terms are tag=, number= (5, 1..10, 1..=9, 100..), name= (a glob), flag=, child-of= and
+    /// reachable-from=, combined with and, or, not and parentheses.
+    /// iso, pack and unpack don't work on chunks, so they don't take it.
+    #[arg(long, global = true)]
+    select: Option<Query>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Export actor templates as glTF binaries.
+    Export {
+        /// Chunky files containing the templates, in load order. Chunks in later files override
+        /// those in earlier ones.
+        #[arg(default_value = "../3DMMForever/content-files/tmpls.3cn")]
+        inputs: Vec<PathBuf>,
+        /// Megabytes of unpacked chunks to keep for reuse. 0 disables the cache.
+        #[arg(long, default_value_t = 64)]
+        cache_size: usize,
+    },
+    /// Load a chunky file and write it back out.
+    Rewrite { input: PathBuf, output: PathBuf },
+    /// List the chunks that own a chunk and the roots it is reachable from.
+    Owners {
+        input: PathBuf,
+        /// Chunk to look up, as TAG:number. Chunks matching --select are looked up as well.
+        chunk: Option<ChunkId>,
+    },
+    /// Decode every chunk whose type is known and print a summary of each.
+    Dump {
+        /// Chunky files to load, in load order.
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+    },
+    /// Write every chunk, unpacked, to TAG/number.bin with its index entry in TAG/number.json.
+    Extract { input: PathBuf, output: PathBuf },
+    /// Build a chunky file from a tree written by extract, packing chunks flagged PACKED again.
+    Rebuild {
+        input: PathBuf,
+        output: PathBuf,
+        /// How hard to search for repeated data when packing, from 0 (not at all) to 9.
+        #[arg(long, default_value = "6", value_parser = parse_level)]
+        level: Level,
+    },
+    /// Write the graph of chunks and their children.
+    Graph {
+        input: PathBuf,
+        /// Only include chunks reachable from this one, given as TAG:number.
+        #[arg(long)]
+        root: Option<ChunkId>,
+        /// Only follow this many links from the root.
+        #[arg(long, requires = "root")]
+        depth: Option<usize>,
+        #[arg(long, value_enum, default_value_t = GraphFormat::Dot)]
+        format: GraphFormat,
+        /// File to write to instead of standard output.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// List the chunks added, removed or changed between two chunky files.
+    Diff { old: PathBuf, new: PathBuf },
+    /// Check a chunky file for broken links, overlapping data and other damage.
+    Fsck { input: PathBuf },
+    /// List the chunky files on an ISO 9660 disc image. Any command can read these directly,
+    /// given a path through the image such as disc.iso/3DMOVIE/TMPLS.3CN.
+    Iso { image: PathBuf },
+    /// Report unindexed space and orphaned chunks, optionally extracting what can be salvaged.
+    /// --select limits which orphans are reported.
+    Recover {
+        input: PathBuf,
+        /// Directory to write recovered data to.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Wrap a file in an apak header, packed with a Kauai codec, or a puak header, stored as is.
+    /// Other commands unwrap such files themselves.
+    Pack {
+        input: PathBuf,
+        output: PathBuf,
+        #[arg(long, value_enum, default_value_t = PackCodec::Kcd2)]
+        codec: PackCodec,
+        /// How hard to search for repeated data, from 0 (not at all) to 9.
+        #[arg(long, default_value = "6", value_parser = parse_level)]
+        level: Level,
+    },
+    /// Unwrap a file with an apak or puak header.
+    Unpack { input: PathBuf, output: PathBuf },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum PackCodec {
+    Kcdc,
+    Kcd2,
+    /// Store the data as is, with a puak header.
+    #[value(name = "none")]
+    Stored,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum GraphFormat {
+    /// Graphviz DOT.
+    Dot,
+    Json,
+}
+
 fn main() -> Result<()> {
-    let tmpls = File::open("../3DMMForever/content-files/tmpls.3cn")?;
-    let tmpls = unsafe { Mmap::map(&tmpls)? };
-    let tmpls = ChunkyFile::load(&tmpls[..])?;
+    let args = Args::parse();
+    let code_page = args.code_page;
+    let select = args.select.as_ref();
+    if select.is_some()
+        && matches!(
+            args.command,
+            Command::Iso { .. } | Command::Pack { .. } | Command::Unpack { .. }
+        )
+    {
+        bail!("This command doesn't work on chunks and doesn't take --select");
+    }
+    match args.command {
+        Command::Export { inputs, cache_size } => {
+            export_templates(&inputs, cache_size, code_page, select)
+        }
+        Command::Rewrite { input, output } => rewrite(&input, &output, code_page, select),
+        Command::Owners { input, chunk } => owners(&input, chunk.as_ref(), code_page, select),
+        Command::Dump { inputs } => dump(&inputs, code_page, select),
+        Command::Extract { input, output } => extract(&input, &output, code_page, select),
+        Command::Rebuild {
+            input,
+            output,
+            level,
+        } => rebuild(&input, &output, level, code_page, select),
+        Command::Graph {
+            input,
+            root,
+            depth,
+            format,
+            output,
+        } => graph(
+            &input,
+            root.as_ref(),
+            depth,
+            format,
+            output.as_deref(),
+            code_page,
+            select,
+        ),
+        Command::Diff { old, new } => diff(&old, &new, code_page, select),
+        Command::Fsck { input } => fsck(&input, code_page, select),
+        Command::Iso { image } => list_iso(&image),
+        Command::Recover { input, output } => {
+            recover(&input, output.as_deref(), code_page, select)
+        }
+        Command::Pack {
+            input,
+            output,
+            codec,
+            level,
+        } => pack(&input, &output, codec, level),
+        Command::Unpack { input, output } => unpack(&input, &output),
+    }
+}
+
+fn parse_level(value: &str) -> Result<Level, String> {
+    value
+        .parse()
+        .ok()
+        .and_then(Level::new)
+        .ok_or_else(|| format!("{value} isn't a level from 0 to 9"))
+}
+
+fn parse_code_page(value: &str) -> Result<&'static Encoding, String> {
+    let number = value
+        .parse()
+        .map_err(|_| format!("{value} isn't a code page number"))?;
+    codepage::to_encoding(number).ok_or_else(|| format!("Code page {value} isn't supported"))
+}
+
+/// A memory mapped input file, or a file on a memory mapped disc image.
+struct Input {
+    map: Mmap,
+    range: Range<usize>,
+    /// The contents of a file wrapped in an apak header, unpacked.
+    unpacked: Option<Vec<u8>>,
+}
+
+impl Deref for Input {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.unpacked {
+            Some(unpacked) => unpacked,
+            None => &self.map[self.range.clone()],
+        }
+    }
+}
+
+/// Maps `path` into memory as [`map_packed_file`] does, then unwraps it if it has an apak or
+/// puak header.
+fn map_file(path: &Path) -> Result<Input> {
+    let mut input = map_packed_file(path)?;
+    match input.get(..4) {
+        Some(kauai::SIGNATURE_STORED) => input.range.start += 4,
+        Some(kauai::SIGNATURE_PACKED) => {
+            let unpacked =
+                kauai::unpack(&input).with_context(|| format!("Unpacking {}", path.display()))?;
+            input.unpacked = Some(unpacked.into_owned());
+        }
+        _ => {}
+    }
+    Ok(input)
+}
+
+/// Maps `path` into memory. A path that runs through an ISO image, like
+/// `disc.iso/3DMOVIE/TMPLS.3CN`, maps the image and reads the file from it.
+fn map_packed_file(path: &Path) -> Result<Input> {
+    let is_image = |p: &Path| {
+        p.is_file()
+            && p.extension()
+                .is_some_and(|e| e.eq_ignore_ascii_case("iso"))
+    };
+    if !path.exists() {
+        if let Some(image) = path.ancestors().skip(1).find(|p| is_image(p)) {
+            let map = map_whole_file(image)?;
+            let inner = path.strip_prefix(image)?;
+            let inner: Vec<_> = inner.iter().map(|c| c.to_string_lossy()).collect();
+            let inner = inner.join("/");
+            let range = {
+                let iso = IsoImage::open(&map)
+                    .with_context(|| format!("Reading {}", image.display()))?;
+                let Some(file) = iso.find(&inner)? else {
+                    bail!("No {inner} in {}", image.display());
+                };
+                file.offset..file.offset + file.data.len()
+            };
+            return Ok(Input {
+                map,
+                range,
+                unpacked: None,
+            });
+        }
+    }
+    let map = map_whole_file(path)?;
+    Ok(Input {
+        range: 0..map.len(),
+        map,
+        unpacked: None,
+    })
+}
+
+fn map_whole_file(path: &Path) -> Result<Mmap> {
+    let file = File::open(path).with_context(|| format!("Opening {}", path.display()))?;
+    Ok(unsafe { Mmap::map(&file)? })
+}
+
+/// Loads memory mapped chunky files into a library, later files overriding earlier ones.
+/// A nonzero `cache_size` gives each file a cache of that many megabytes.
+fn load_library<'a>(
+    inputs: &[PathBuf],
+    maps: &'a [Input],
+    cache_size: usize,
+    code_page: &'static Encoding,
+) -> Result<Library<'a>> {
+    let mut library = Library::new();
+    for (input, map) in inputs.iter().zip(maps) {
+        let mut file = ChunkyFile::load(&map[..])
+            .with_context(|| format!("Loading {}", input.display()))?
+            .with_code_page(code_page);
+        if cache_size > 0 {
+            file = file.with_cache(cache_size << 20);
+        }
+        library.push(file);
+    }
+    Ok(library)
+}
+
+fn rewrite(
+    input: &Path,
+    output: &Path,
+    code_page: &'static Encoding,
+    select: Option<&Query>,
+) -> Result<()> {
+    let input = map_file(input)?;
+    let file = ChunkyFile::load(&input[..])?.with_code_page(code_page);
+    let mut writer = file.to_writer()?;
+    if let Some(select) = select {
+        let selected: HashSet<_> = select.select_file(&file).iter().map(|e| e.id).collect();
+        writer.retain(|id| selected.contains(id));
+    }
+    let mut output = BufWriter::new(File::create(output)?);
+    writer.write_to(&mut output)?;
+    output.flush()?;
+    Ok(())
+}
+
+fn owners(
+    input: &Path,
+    chunk: Option<&ChunkId>,
+    code_page: &'static Encoding,
+    select: Option<&Query>,
+) -> Result<()> {
+    let input = map_file(input)?;
+    let file = ChunkyFile::load(&input[..])?.with_code_page(code_page);
+    let mut chunks = Vec::new();
+    if let Some(chunk) = chunk {
+        if !file.index.contains_key(chunk) {
+            bail!("No chunk {chunk}");
+        }
+        chunks.push(*chunk);
+    }
+    if let Some(select) = select {
+        chunks.extend(select.select_file(&file).iter().map(|e| e.id));
+    }
+    if chunk.is_none() && select.is_none() {
+        bail!("Give a chunk to look up or --select");
+    }
+    chunks.sort_unstable();
+    chunks.dedup();
+
+    for chunk in &chunks {
+        println!("{chunk} {:?}", file.index[chunk].name);
+        for owner in file.owners(chunk) {
+            println!("  owned by {} as child {}", owner.chunk_id, owner.child_id);
+        }
+        if file.is_orphan(chunk) {
+            println!("  orphaned");
+        }
+        for root in file.roots_of(chunk) {
+            println!("  reachable from root {root}");
+        }
+    }
+    println!("{} roots in file", file.roots().count());
+    Ok(())
+}
+
+fn dump(inputs: &[PathBuf], code_page: &'static Encoding, select: Option<&Query>) -> Result<()> {
+    let maps = inputs.iter().map(|i| map_file(i)).collect::<Result<Vec<_>>>()?;
+    let library = load_library(inputs, &maps, 0, code_page)?;
+    dump_library(&library, select, 0);
+    Ok(())
+}
+
+/// The chunks in `library` that match `select`, or all of them, in chunk ID order.
+fn select_entries<'l, 'a>(
+    library: &'l Library<'a>,
+    select: Option<&Query>,
+) -> Vec<&'l IndexEntry<'a>> {
+    match select {
+        Some(select) => select.select(library),
+        None => {
+            let mut entries: Vec<_> = library.entries().collect();
+            entries.sort_unstable_by_key(|e| e.id);
+            entries
+        }
+    }
+}
+
+/// Prints each chunk in `library`, followed by the contents of any forests, indented.
+/// Forests are only shown if they are selected themselves, and the same query then chooses the
+/// chunks shown inside them.
+fn dump_library(library: &Library, select: Option<&Query>, indent: usize) {
+    for entry in select_entries(library, select) {
+        match library.decode_chunk(entry) {
+            Ok(chunk) => println!("{:indent$}{} {:?} {chunk}", "", entry.id, entry.name),
+            Err(e) => println!("{:indent$}{} {:?} error: {e}", "", entry.id, entry.name),
+        }
+        if entry.flags.contains(ChunkFlags::FOREST) {
+            let nested = library.forest(entry).and_then(|forest| {
+                let mut nested = Library::new();
+                nested.push(forest.file()?);
+                dump_library(&nested, select, indent + 4);
+                Ok(())
+            });
+            if let Err(e) = nested {
+                println!("{:indent$}    forest error: {e}", "");
+            }
+        }
+    }
+}
+
+fn extract(
+    input: &Path,
+    output: &Path,
+    code_page: &'static Encoding,
+    select: Option<&Query>,
+) -> Result<()> {
+    let input = map_file(input)?;
+    let file = ChunkyFile::load(&input[..])?.with_code_page(code_page);
+    let entries = match select {
+        Some(select) => select.select_file(&file),
+        None => file.index.values().collect(),
+    };
+    let undecoded = tree::extract(&file, &entries, output)?;
+    for id in &undecoded {
+        eprintln!("warning: couldn't unpack {id}, wrote it as stored");
+    }
+    println!("Extracted {} chunks", entries.len());
+    Ok(())
+}
+
+fn rebuild(
+    input: &Path,
+    output: &Path,
+    level: Level,
+    code_page: &'static Encoding,
+    select: Option<&Query>,
+) -> Result<()> {
+    let writer = tree::rebuild(input, level, code_page)?;
+    let mut rebuilt = Vec::new();
+    writer.write_to(&mut rebuilt)?;
+    if let Some(select) = select {
+        // Queries work on index entries, so read what was built to choose the chunks to keep.
+        let file = ChunkyFile::load(&rebuilt)?.with_code_page(code_page);
+        let selected: HashSet<_> = select.select_file(&file).iter().map(|e| e.id).collect();
+        let mut writer = file.to_writer()?;
+        writer.retain(|id| selected.contains(id));
+        let mut kept = Vec::new();
+        writer.write_to(&mut kept)?;
+        rebuilt = kept;
+    }
+    std::fs::write(output, rebuilt)?;
+    Ok(())
+}
+
+fn graph(
+    input: &Path,
+    root: Option<&ChunkId>,
+    depth: Option<usize>,
+    format: GraphFormat,
+    output: Option<&Path>,
+    code_page: &'static Encoding,
+    select: Option<&Query>,
+) -> Result<()> {
+    let input = map_file(input)?;
+    let file = ChunkyFile::load(&input[..])?.with_code_page(code_page);
+    if let Some(root) = root {
+        if !file.index.contains_key(root) {
+            bail!("No chunk {root}");
+        }
+    }
+    let mut entries = graph::select(&file, root, depth);
+    if let Some(select) = select {
+        let selected: HashSet<_> = select.select_file(&file).iter().map(|e| e.id).collect();
+        entries.retain(|e| selected.contains(&e.id));
+    }
+
+    let mut output: Box<dyn Write> = match output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    match format {
+        GraphFormat::Dot => graph::write_dot(&file, &entries, &mut output)?,
+        GraphFormat::Json => {
+            serde_json::to_writer_pretty(&mut output, &graph::to_json(&entries))?;
+            writeln!(output)?;
+        }
+    }
+    output.flush()?;
+    Ok(())
+}
+
+fn diff(
+    old: &Path,
+    new: &Path,
+    code_page: &'static Encoding,
+    select: Option<&Query>,
+) -> Result<()> {
+    let old = map_file(old)?;
+    let old = ChunkyFile::load(&old[..])?.with_code_page(code_page);
+    let new = map_file(new)?;
+    let new = ChunkyFile::load(&new[..])?.with_code_page(code_page);
+    // A chunk counts as selected if it matches in either file.
+    let selected: Option<HashSet<_>> = select.map(|select| {
+        (select.select_file(&old).into_iter())
+            .chain(select.select_file(&new))
+            .map(|e| e.id)
+            .collect()
+    });
+    for change in diff::diff(&old, &new) {
+        if selected.as_ref().is_none_or(|s| s.contains(change.id())) {
+            println!("{change}");
+        }
+    }
+    Ok(())
+}
+
+fn fsck(input: &Path, code_page: &'static Encoding, select: Option<&Query>) -> Result<()> {
+    let input = map_file(input)?;
+    let file = ChunkyFile::load(&input[..])?.with_code_page(code_page);
+    let problems = match select {
+        Some(select) => {
+            let ids: Vec<_> = select.select_file(&file).iter().map(|e| e.id).collect();
+            fsck::check_chunks(&file, &ids)
+        }
+        None => fsck::check(&file),
+    };
+    for problem in &problems {
+        println!("{problem}");
+    }
+    if !problems.is_empty() {
+        bail!("Found {} problems", problems.len());
+    }
+    Ok(())
+}
+
+fn list_iso(image: &Path) -> Result<()> {
+    let map = map_whole_file(image)?;
+    let iso = IsoImage::open(&map)?;
+    let listing = iso.chunky_files();
+    for (path, e) in &listing.errors {
+        let path = if path.is_empty() { "/" } else { path };
+        println!("{path} error: {e}");
+    }
+    for file in listing.files {
+        match ChunkyFile::load(file.data) {
+            Ok(chunky) => println!(
+                "{} {} bytes, {} chunks by {}",
+                file.path,
+                file.data.len(),
+                chunky.index.len(),
+                chunky.creator,
+            ),
+            Err(e) => println!("{} {} bytes, error: {e}", file.path, file.data.len()),
+        }
+    }
+    Ok(())
+}
+
+fn pack(input: &Path, output: &Path, codec: PackCodec, level: Level) -> Result<()> {
+    // Pack the bytes as given, even if they're already wrapped.
+    let input = map_packed_file(input)?;
+    let codec = match codec {
+        PackCodec::Kcdc => Some(&b"KCDC"[..]),
+        PackCodec::Kcd2 => Some(&b"KCD2"[..]),
+        PackCodec::Stored => None,
+    };
+    std::fs::write(output, kauai::pack(codec, &input, level)?)?;
+    Ok(())
+}
+
+fn unpack(input: &Path, output: &Path) -> Result<()> {
+    let input = map_packed_file(input)?;
+    std::fs::write(output, kauai::unpack(&input)?)?;
+    Ok(())
+}
+
+fn recover(
+    input: &Path,
+    output: Option<&Path>,
+    code_page: &'static Encoding,
+    select: Option<&Query>,
+) -> Result<()> {
+    let input = map_file(input)?;
+    let file = ChunkyFile::load(&input[..])?.with_code_page(code_page);
+    let mut recovery = recover::scan(&file);
+    if let Some(select) = select {
+        let selected: HashSet<_> = select.select_file(&file).iter().map(|e| e.id).collect();
+        recovery.orphans.retain(|id| selected.contains(id));
+    }
+
+    for warning in &recovery.warnings {
+        eprintln!("warning: {warning}");
+    }
+    for region in &recovery.regions {
+        println!(
+            "{} {:#x}..{:#x} ({} bytes)",
+            region.source,
+            region.range.start,
+            region.range.end,
+            region.range.len(),
+        );
+    }
+    for blob in &recovery.packed {
+        println!(
+            "packed {} at {:#x} ({} bytes unpacked)",
+            blob.codec.escape_ascii(),
+            blob.offset,
+            blob.data.len(),
+        );
+    }
+    for id in &recovery.orphans {
+        println!("orphan {id} {:?}", file.index[id].name);
+    }
 
-    tmpls.index.par_iter().filter(|(key, value)| key.tag == "TMPL" && value.flags.contains(ChunkFlags::LONER) /* && value.name == "Willy" */).try_for_each(|(_, value)| {
+    let Some(output) = output else {
+        return Ok(());
+    };
+    std::fs::create_dir_all(output)?;
+    for region in &recovery.regions {
+        std::fs::write(
+            output.join(format!("{}-{:08x}.bin", region.source, region.range.start)),
+            &file.data[region.range.clone()],
+        )?;
+    }
+    for blob in &recovery.packed {
+        std::fs::write(
+            output.join(format!("packed-{:08x}.bin", blob.offset)),
+            &blob.data,
+        )?;
+    }
+    for id in &recovery.orphans {
+        let data = file
+            .get_chunk(&file.index[id])
+            .with_context(|| format!("Reading orphan {id}"))?;
+        std::fs::write(
+            output.join(format!("orphan-{}-{}.bin", id.tag, id.number.get())),
+            data,
+        )?;
+    }
+    Ok(())
+}
+
+fn export_templates(
+    inputs: &[PathBuf],
+    cache_size: usize,
+    code_page: &'static Encoding,
+    select: Option<&Query>,
+) -> Result<()> {
+    let maps = inputs.iter().map(|i| map_file(i)).collect::<Result<Vec<_>>>()?;
+    let library = load_library(inputs, &maps, cache_size, code_page)?;
+    export_library(&library, select)?;
+
+    for (input, file) in inputs.iter().zip(library.files()) {
+        if let Some(cache) = &file.cache {
+            let (hits, misses) = cache.stats();
+            eprintln!("{}: chunk cache {hits} hits, {misses} misses", input.display());
+        }
+    }
+
+    Ok(())
+}
+
+/// Exports the selected templates in `library`, then those in any forests, each forest on its
+/// own.
+fn export_library(library: &Library, select: Option<&Query>) -> Result<()> {
+    let templates: Vec<_> = select_entries(library, select)
+        .into_iter()
+        .filter(|value| value.id.tag == "TMPL" && value.flags.contains(ChunkFlags::LONER))
+        .collect();
+    templates.par_iter().try_for_each(|value| {
         dbg!(&value.name);
-        let data = tmpls.get_chunk(value)?;
-        let _tmpl = Template::load(&data)?;
+        let _tmpl = library.load_chunk::<Template>(value)?;
 
-        let Some(armature) = value.get_child(0, "GLPI").and_then(|c| tmpls.index.get(c)) else {
-            bail!("No GLPI");
-        };
-        let armature = Armature::load(&tmpls.get_chunk(armature)?)?;
+        let armature = library.get_child_entry(value, 0, "GLPI")?;
+        let armature = library.load_chunk::<Armature>(armature)?;
 
-        let Some(body_part_sets) = value.get_child(0, "GLBS").and_then(|c| tmpls.index.get(c)) else {
-            bail!("No GLBS");
-        };
-        let body_part_sets = BodyPartSets::load(&tmpls.get_chunk(body_part_sets)?)?;
+        let body_part_sets = library.get_child_entry(value, 0, "GLBS")?;
+        let body_part_sets = library.load_chunk::<BodyPartSets>(body_part_sets)?;
 
-        let Some(costumes) = value.get_child(0, "GGCM").and_then(|c| tmpls.index.get(c)) else {
-            bail!("No GGCM");
-        };
-        let costumes = tmpls.get_chunk(costumes)?;
-        let costumes = Costumes::load(&costumes)?;
+        let costumes = library.get_child_entry(value, 0, "GGCM")?;
+        let costumes = library.load_chunk::<Costumes>(costumes)?;
 
         let materials: HashMap<u32, CustomMaterialData> = costumes.part_sets.par_iter().enumerate().flat_map(|(set_index, set)| {
             let group_size = body_part_sets
@@ -127,36 +746,30 @@ fn main() -> Result<()> {
                 .filter(|g| **g as usize == set_index)
                 .count();
 
-            let tmpls = &tmpls;
+            let library = &library;
             set.par_iter().map(move |material_index| {
-                let Some(custom_material) = value.get_child(*material_index, "CMTL").and_then(|c| tmpls.index.get(c)) else {
-                    bail!("Missing CMTL {material_index}");
-                };
+                let custom_material = library.get_child_entry(value, *material_index, "CMTL")?;
                 let mut accessories = HashMap::new();
                 for child in custom_material.children.iter().filter(|c| c.chunk_id.tag == "BMDL") {
-                    let Some(chunk) = tmpls.index.get(&child.chunk_id) else {
+                    let Some(chunk) = library.get(&child.chunk_id) else {
                         bail!("Missing accessory data {child:?} for material {material_index}");
                     };
-                    let model = Model::load(&tmpls.get_chunk(chunk)?)?;
+                    let model = library.load_chunk::<Model>(chunk)?;
                     accessories.insert(child.child_id, model);
                 }
 
                 let load_material = |part_index| {
-                    let Some(material_chunk) = custom_material.get_child(part_index, "MTRL").and_then(|c| tmpls.index.get(c)) else {
-                        bail!("Missing material {material_index} {part_index}");
-                    };
-                    let material = mtrl::Material::load(&tmpls.get_chunk(material_chunk)?)?;
+                    let material_chunk = library.get_child_entry(custom_material, part_index, "MTRL")?;
+                    let material = library.load_chunk::<mtrl::Material>(material_chunk)?;
                     let texture_map = material_chunk.get_child(0, "TMAP").copied();
 
                     let texture_transform = if let Some(texture_transform) =
                         material_chunk.get_child(0, "TXXF")
                     {
-                        let Some(texture_transform) = tmpls.index.get(texture_transform) else {
+                        let Some(texture_transform) = library.get(texture_transform) else {
                             bail!("Missing texture transform for material {material_index} {part_index}");
                         };
-                        Some(txxf::TextureTransform::load(
-                            &tmpls.get_chunk(texture_transform)?,
-                        )?)
+                        Some(library.load_chunk::<txxf::TextureTransform>(texture_transform)?)
                     } else {
                         None
                     };
@@ -175,10 +788,10 @@ fn main() -> Result<()> {
                 }).collect::<Result<Vec<_>>>()?;
 
                 let textures = parts.par_iter().filter_map(|p| p.texture_map).map(|texture_id| {
-                    let Some(texture_map) = tmpls.index.get(&texture_id) else {
+                    let Some(texture_map) = library.get(&texture_id) else {
                         bail!("Missing texture map for material {material_index}");
                     };
-                    Ok((texture_id, TextureMap::load(&tmpls.get_chunk(texture_map)?)?))
+                    Ok((texture_id, library.load_chunk::<TextureMap>(texture_map)?))
                 }).collect::<Result<HashMap<_, _>>>()?;
 
                 Ok((*material_index, CustomMaterialData {
@@ -194,26 +807,20 @@ fn main() -> Result<()> {
             .par_iter()
             .filter(|c| c.chunk_id.tag == "BMDL")
             .map(|model_link| {
-                let Some(model) = tmpls.index.get(&model_link.chunk_id) else {
+                let Some(model) = library.get(&model_link.chunk_id) else {
                 bail!("Missing model {}", model_link.child_id);
             };
-                let model = Model::load(&tmpls.get_chunk(model)?)?;
+                let model = library.load_chunk::<Model>(model)?;
                 Ok((model_link.child_id, ModelData { model }))
             })
             .collect::<Result<HashMap<u32, ModelData>>>()?;
 
-        let Some(action) = value.get_child(0, "ACTN").and_then(|c| tmpls.index.get(c)) else {
-            bail!("No default action");
-        };
-        let Some(action_cells) = action.get_child(0, "GGCL").and_then(|c| tmpls.index.get(c)) else {
-            bail!("No default action GGCL");
-        };
-        let action_cells = AnimationCells::load(&tmpls.get_chunk(action_cells)?)?;
-        let Some(action_transforms) = action.get_child(0, "GLXF").and_then(|c| tmpls.index.get(c)) else {
-            bail!("No default action GLXF");
-        };
+        let action = library.get_child_entry(value, 0, "ACTN")?;
+        let action_cells = library.get_child_entry(action, 0, "GGCL")?;
+        let action_cells = library.load_chunk::<AnimationCells>(action_cells)?;
+        let action_transforms = library.get_child_entry(action, 0, "GLXF")?;
         let action_transforms =
-            AnimationTransforms::load(&tmpls.get_chunk(action_transforms)?)?;
+            library.load_chunk::<AnimationTransforms>(action_transforms)?;
 
         let mut template = TemplateData {
             armature,
@@ -232,9 +839,19 @@ fn main() -> Result<()> {
             &template,
         )?;
 
-        Ok(())
+        anyhow::Ok(())
     })?;
 
+    for entry in library
+        .entries()
+        .filter(|e| e.flags.contains(ChunkFlags::FOREST))
+    {
+        let forest = library.forest(entry)?;
+        let mut nested = Library::new();
+        nested.push(forest.file()?);
+        export_library(&nested, select).with_context(|| format!("Exporting forest {}", entry.id))?;
+    }
+
     Ok(())
 }
 
@@ -1146,7 +1763,7 @@ fn export_model(name: &str, template: &TemplateData) -> Result<()> {
         });
 
         // the index buffer length might not be a multiple of four but the next buffer must start at a four byte alignment.
-        buffer.extend(iter::repeat(0).take(3 - (buffer.len() + 3) % 4));
+        buffer.extend(iter::repeat_n(0, 3 - (buffer.len() + 3) % 4));
 
         let indices = Index::new(doc.accessors.len() as u32);
         doc.accessors.push(Accessor {
@@ -1,6 +1,5 @@
 use std::mem;
 
-use anyhow::{bail, ensure, Result};
 use byteorder::ByteOrder;
 use nalgebra::{point, vector, Point2, Point3, Vector3};
 use rgb::RGB8;
@@ -8,6 +7,7 @@ use zerocopy::{FromBytes, U16, U32};
 
 use crate::{
     brender::{Fraction, Scalar},
+    error::{Error, Result},
     order::Loader,
 };
 
@@ -150,9 +150,9 @@ where
 
 #[derive(Debug)]
 pub struct Model {
-    pub _radius: f64,
+    pub radius: f64,
     pub bounds: Bounds,
-    pub _pivot: Point3<f64>,
+    pub pivot: Point3<f64>,
     pub vertices: Vec<Vertex>,
     pub faces: Vec<Face>,
 }
@@ -256,7 +256,7 @@ impl<'a> Loader<'a> for Model {
         let mut vertices = Vec::with_capacity(data.vertex_count.get() as usize);
         for _ in 0..data.vertex_count.get() {
             let Some(vertex) = VertexOnFile::<O>::read_from_prefix(input) else {
-                bail!("EOF in vertices");
+                return Err(Error::truncated("vertices", full_input.len() - input.len()));
             };
             input = &input[mem::size_of::<VertexOnFile<O>>()..];
             let vertex: Vertex = vertex.into();
@@ -279,13 +279,25 @@ impl<'a> Loader<'a> for Model {
         let mut faces: Vec<Face> = Vec::with_capacity(data.face_count.get() as usize);
         for _ in 0..data.face_count.get() {
             let Some(face) = FaceOnFile::<O>::read_from_prefix(input) else {
-                bail!("EOF in faces");
+                return Err(Error::truncated("faces", full_input.len() - input.len()));
             };
+            let face: Face = face.into();
+            if face.vertices.iter().any(|&v| v as usize >= vertices.len()) {
+                return Err(Error::invalid(
+                    "Face refers to a missing vertex",
+                    full_input.len() - input.len(),
+                ));
+            }
             input = &input[mem::size_of::<FaceOnFile<O>>()..];
-            faces.push(face.into());
+            faces.push(face);
         }
 
-        ensure!(input.is_empty(), "Did not read complete model");
+        if !input.is_empty() {
+            return Err(Error::invalid(
+                "Did not read complete model",
+                full_input.len() - input.len(),
+            ));
+        }
 
         // Port of BRender 1.3.2 prepmesh code for normal calculation.
         // sitobren is supposed to do this, but I can't go back in time 28 years to fix it.
@@ -390,9 +402,9 @@ impl<'a> Loader<'a> for Model {
         }
 
         Ok(Model {
-            _radius: data.radius.into(),
+            radius: data.radius.into(),
             bounds: bounds.unwrap_or_default(),
-            _pivot: data.pivot.into(),
+            pivot: data.pivot.into(),
             vertices,
             faces,
         })
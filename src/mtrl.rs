@@ -1,9 +1,9 @@
-use anyhow::Result;
 use byteorder::ByteOrder;
 use zerocopy::{FromBytes, U16, U32};
 
 use crate::{
     brender::{Scalar, UFraction},
+    error::Result,
     order::Loader,
 };
 
@@ -1,7 +1,8 @@
-use anyhow::{bail, Result};
 use byteorder::{BigEndian, ByteOrder, LittleEndian};
 use zerocopy::FromBytes;
 
+use crate::error::{Error, ErrorKind, Result};
+
 pub const BYTE_ORDER_NATIVE: u16 = 0x0001;
 pub const BYTE_ORDER_SWAPPED: u16 = 0x0100;
 
@@ -10,6 +11,9 @@ pub trait Loader<'a>: 'a + Sized {
     where
         O: ByteOrder;
 
+    /// Where the byte order is in `OnFile`. Kauai's structures start with it.
+    const BYTE_ORDER_OFFSET: usize = 0;
+
     fn byte_order<O>(on_file: &Self::OnFile<O>) -> u16
     where
         O: ByteOrder;
@@ -20,21 +24,20 @@ pub trait Loader<'a>: 'a + Sized {
 
     fn load(full_input: &'a [u8]) -> Result<Self> {
         let Some(on_disk) = Self::OnFile::<LittleEndian>::read_from_prefix(full_input) else {
-            bail!("{} input too small", std::any::type_name::<Self>())
+            return Err(Error::truncated(std::any::type_name::<Self>(), full_input.len()));
         };
         match Self::byte_order(&on_disk) {
             BYTE_ORDER_NATIVE => Self::into_native(on_disk, full_input),
             BYTE_ORDER_SWAPPED => {
                 let Some(on_disk) = Self::OnFile::<BigEndian>::read_from_prefix(full_input) else {
-                    bail!("{} input too small", std::any::type_name::<Self>())
+                    return Err(Error::truncated(std::any::type_name::<Self>(), full_input.len()));
                 };
                 Self::into_native(on_disk, full_input)
             }
-            other => bail!(
-                "Unexpected byte order {:04x} in {}",
-                other,
-                std::any::type_name::<Self>(),
-            ),
+            other => Err(Error::new(
+                ErrorKind::ByteOrder(other),
+                Self::BYTE_ORDER_OFFSET,
+            )),
         }
     }
 }
@@ -0,0 +1,378 @@
+use std::{
+    collections::{HashMap, HashSet},
+    iter::Peekable,
+    ops::Range,
+    str::{CharIndices, FromStr},
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    chunky::{ChunkFlags, ChunkId, ChunkTag, ChunkyFile, IndexEntry},
+    library::Library,
+};
+
+/// A filter choosing chunks, parsed from expressions like
+/// `tag=TMPL and flag=LONER and not name="Willy*"`.
+///
+/// Terms are `key=value`, combined with `and`, `or`, `not` and parentheses:
+///
+/// - `tag=TMPL`
+/// - `number=5`, `number=0x10..0x20` (end excluded), `number=1..=9`, `number=100..`
+/// - `name=Will*`, a glob where `*` matches any run of characters and `?` any one, ignoring case
+/// - `flag=LONER`
+/// - `child-of=ACTN:1`, the chunks `ACTN:1` links to directly
+/// - `reachable-from=TMPL:3`, `TMPL:3` and everything it links to, directly or not
+///
+/// Values containing spaces or parentheses can be quoted, as in `tag="GST "`.
+#[derive(Clone, Debug)]
+pub enum Query {
+    Tag(ChunkTag),
+    Number(Range<u64>),
+    Name(String),
+    Flag(ChunkFlags),
+    Linked(Relation, ChunkId),
+    Not(Box<Query>),
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Relation {
+    ChildOf,
+    ReachableFrom,
+}
+
+impl Query {
+    /// The chunks in `library` that match, in chunk ID order.
+    pub fn select<'l, 'a>(&self, library: &'l Library<'a>) -> Vec<&'l IndexEntry<'a>> {
+        self.filter(library.entries(), |id| library.get(id))
+    }
+
+    /// The chunks in `file` that match, in chunk ID order.
+    pub fn select_file<'f, 'a>(&self, file: &'f ChunkyFile<'a>) -> Vec<&'f IndexEntry<'a>> {
+        self.filter(file.index.values(), |id| file.index.get(id))
+    }
+
+    fn filter<'e, 'a: 'e>(
+        &self,
+        entries: impl Iterator<Item = &'e IndexEntry<'a>>,
+        get: impl Fn(&ChunkId) -> Option<&'e IndexEntry<'a>>,
+    ) -> Vec<&'e IndexEntry<'a>> {
+        let mut links = HashMap::new();
+        self.collect_links(&get, &mut links);
+        let mut entries: Vec<_> = entries.filter(|e| self.matches(e, &links)).collect();
+        entries.sort_unstable_by_key(|e| e.id);
+        entries
+    }
+
+    /// Works out which chunks each `child-of` and `reachable-from` term covers.
+    fn collect_links<'e, 'a: 'e>(
+        &self,
+        get: &impl Fn(&ChunkId) -> Option<&'e IndexEntry<'a>>,
+        links: &mut HashMap<(Relation, ChunkId), HashSet<ChunkId>>,
+    ) {
+        match self {
+            Query::Linked(relation, root) => {
+                links.entry((*relation, *root)).or_insert_with(|| {
+                    let children = |id: &ChunkId| {
+                        get(id)
+                            .into_iter()
+                            .flat_map(|e| e.children.iter().map(|c| c.chunk_id))
+                    };
+                    match relation {
+                        Relation::ChildOf => children(root).collect(),
+                        Relation::ReachableFrom => {
+                            let mut reached = HashSet::new();
+                            let mut pending = vec![*root];
+                            while let Some(id) = pending.pop() {
+                                if reached.insert(id) {
+                                    pending.extend(children(&id));
+                                }
+                            }
+                            reached
+                        }
+                    }
+                });
+            }
+            Query::Not(query) => query.collect_links(get, links),
+            Query::And(a, b) | Query::Or(a, b) => {
+                a.collect_links(get, links);
+                b.collect_links(get, links);
+            }
+            Query::Tag(_) | Query::Number(_) | Query::Name(_) | Query::Flag(_) => {}
+        }
+    }
+
+    fn matches(
+        &self,
+        entry: &IndexEntry,
+        links: &HashMap<(Relation, ChunkId), HashSet<ChunkId>>,
+    ) -> bool {
+        match self {
+            Query::Tag(tag) => entry.id.tag == *tag,
+            Query::Number(range) => range.contains(&(entry.id.number.get() as u64)),
+            Query::Name(glob) => glob_matches(glob, &entry.name),
+            Query::Flag(flag) => entry.flags.contains(*flag),
+            Query::Linked(relation, root) => links[&(*relation, *root)].contains(&entry.id),
+            Query::Not(query) => !query.matches(entry, links),
+            Query::And(a, b) => a.matches(entry, links) && b.matches(entry, links),
+            Query::Or(a, b) => a.matches(entry, links) || b.matches(entry, links),
+        }
+    }
+}
+
+/// Matches `text` against a glob of `*` and `?` wildcards, ignoring case.
+fn glob_matches(glob: &str, text: &str) -> bool {
+    let glob: Vec<_> = glob.chars().flat_map(char::to_lowercase).collect();
+    let text: Vec<_> = text.chars().flat_map(char::to_lowercase).collect();
+    // Where to resume after the last `*`, if the characters after it stop matching.
+    let mut backtrack = None;
+    let (mut g, mut t) = (0, 0);
+    while t < text.len() {
+        match glob.get(g) {
+            Some('*') => {
+                backtrack = Some((g, t));
+                g += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                g += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, start)) => {
+                    g = star + 1;
+                    t = start + 1;
+                    backtrack = Some((star, start + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|c| *c == '*')
+}
+
+impl FromStr for Query {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?.into_iter().peekable(),
+        };
+        let query = parser.or()?;
+        if let Some(token) = parser.tokens.next() {
+            bail!("Unexpected {token:?}");
+        }
+        Ok(query)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Word(String),
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars: Peekable<CharIndices> = s.char_indices().peekable();
+    while let Some(&(_, c)) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&(i, c)) = chars.peek() {
+                    if c.is_whitespace() || c == '(' || c == ')' {
+                        break;
+                    }
+                    chars.next();
+                    if c != '"' {
+                        word.push(c);
+                        continue;
+                    }
+                    loop {
+                        match chars.next() {
+                            Some((_, '"')) => break,
+                            Some((_, c)) => word.push(c),
+                            None => bail!("Unterminated quote at {i}"),
+                        }
+                    }
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser<I>
+where
+    I: Iterator<Item = Token>,
+{
+    tokens: Peekable<I>,
+}
+
+impl<I> Parser<I>
+where
+    I: Iterator<Item = Token>,
+{
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found = matches!(
+            self.tokens.peek(),
+            Some(Token::Word(w)) if w.eq_ignore_ascii_case(keyword)
+        );
+        if found {
+            self.tokens.next();
+        }
+        found
+    }
+
+    fn or(&mut self) -> Result<Query> {
+        let mut query = self.and()?;
+        while self.keyword("or") {
+            query = Query::Or(Box::new(query), Box::new(self.and()?));
+        }
+        Ok(query)
+    }
+
+    fn and(&mut self) -> Result<Query> {
+        let mut query = self.unary()?;
+        while self.keyword("and") {
+            query = Query::And(Box::new(query), Box::new(self.unary()?));
+        }
+        Ok(query)
+    }
+
+    fn unary(&mut self) -> Result<Query> {
+        if self.keyword("not") {
+            return Ok(Query::Not(Box::new(self.unary()?)));
+        }
+        match self.tokens.next() {
+            Some(Token::Open) => {
+                let query = self.or()?;
+                match self.tokens.next() {
+                    Some(Token::Close) => Ok(query),
+                    _ => bail!("Expected )"),
+                }
+            }
+            Some(Token::Word(word)) => term(&word),
+            Some(Token::Close) => bail!("Unexpected )"),
+            None => bail!("Expected a term"),
+        }
+    }
+}
+
+fn term(word: &str) -> Result<Query> {
+    let Some((key, value)) = word.split_once('=') else {
+        bail!("Expected key=value, got {word:?}");
+    };
+    Ok(match key.to_ascii_lowercase().as_str() {
+        "tag" => Query::Tag(value.parse()?),
+        "number" => Query::Number(number_range(value)?),
+        "name" => Query::Name(value.to_owned()),
+        "flag" => Query::Flag(
+            ChunkFlags::from_name(&value.to_ascii_uppercase())
+                .with_context(|| format!("Unknown flag {value}"))?,
+        ),
+        "child-of" => Query::Linked(Relation::ChildOf, value.parse()?),
+        "reachable-from" => Query::Linked(Relation::ReachableFrom, value.parse()?),
+        _ => bail!("Unknown key {key:?}"),
+    })
+}
+
+/// Parses `n`, `a..b`, `a..=b`, `a..` or `..b`.
+fn number_range(value: &str) -> Result<Range<u64>> {
+    let number = |n: &str| -> Result<u64> {
+        let n = match n.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => n.parse(),
+        };
+        Ok(n.with_context(|| format!("Invalid chunk number range {value:?}"))? as u64)
+    };
+    let Some((start, end)) = value.split_once("..") else {
+        let n = number(value)?;
+        return Ok(n..n + 1);
+    };
+    let start = if start.is_empty() { 0 } else { number(start)? };
+    let end = match end.strip_prefix('=') {
+        Some(end) => number(end)? + 1,
+        None if end.is_empty() => u32::MAX as u64 + 1,
+        None => number(end)?,
+    };
+    Ok(start..end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunky::testing::{chunk, file, id, link},
+        order::Loader,
+    };
+
+    #[test]
+    fn globs() {
+        assert!(glob_matches("will*", "Willy"));
+        assert!(glob_matches("*a*b", "xaxxab"));
+        assert!(glob_matches("?illy", "Billy"));
+        assert!(!glob_matches("*a*b", "xaxxa"));
+        assert!(!glob_matches("Will", "Willy"));
+    }
+
+    #[test]
+    fn selects_chunks() {
+        let output = file([
+            (
+                "TMPL:1",
+                chunk(ChunkFlags::LONER, "Willy", vec![link("ACTN:3", 0)], b""),
+            ),
+            (
+                "TMPL:2",
+                chunk(ChunkFlags::LONER, "Fishface", Vec::new(), b""),
+            ),
+            (
+                "ACTN:3",
+                chunk(ChunkFlags::empty(), "walk", vec![link("GGCL:4", 0)], b""),
+            ),
+            ("GGCL:4", chunk(ChunkFlags::PACKED, "", Vec::new(), b"")),
+        ]);
+        let file = ChunkyFile::load(&output).unwrap();
+
+        let select = |query: &str| -> Vec<ChunkId> {
+            let query: Query = query.parse().unwrap();
+            query.select_file(&file).iter().map(|e| e.id).collect()
+        };
+        assert_eq!(
+            select("tag=TMPL and flag=loner"),
+            [id("TMPL:1"), id("TMPL:2")]
+        );
+        assert_eq!(select("name=\"will*\""), [id("TMPL:1")]);
+        assert_eq!(select("number=2..=3"), [id("ACTN:3"), id("TMPL:2")]);
+        assert_eq!(
+            select("reachable-from=TMPL:1 and not (tag=TMPL or flag=PACKED)"),
+            [id("ACTN:3")]
+        );
+        assert_eq!(select("child-of=ACTN:3"), [id("GGCL:4")]);
+
+        for invalid in [
+            "tag=TMPL and",
+            "(tag=TMPL",
+            "colour=red",
+            "number=x",
+            "name=\"a",
+        ] {
+            assert!(invalid.parse::<Query>().is_err(), "{invalid}");
+        }
+    }
+}
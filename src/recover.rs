@@ -0,0 +1,312 @@
+use std::{fmt, mem, ops::Range};
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use zerocopy::{FromBytes, U32};
+
+use crate::{
+    chunky::{ChunkId, ChunkyFile, Prefix},
+    error::Result,
+    glf::{List, ListOnFile},
+    kauai,
+    order::Loader,
+};
+
+/// The best compression ratio we expect from Kauai codecs. Candidates claiming more are assumed to
+/// be false positives, which saves decoding them into enormous buffers.
+const MAX_EXPANSION: usize = 1024;
+
+#[derive(FromBytes)]
+#[repr(C)]
+struct FreeSpaceOnFile<O>
+where
+    O: ByteOrder,
+{
+    offset: U32<O>,
+    length: U32<O>,
+}
+
+struct FreeMap(Vec<Range<usize>>);
+
+impl<'a> Loader<'a> for FreeMap {
+    type OnFile<O> = ListOnFile<O>
+    where
+        O: ByteOrder;
+
+    fn byte_order<O>(on_file: &Self::OnFile<O>) -> u16
+    where
+        O: ByteOrder,
+    {
+        List::byte_order(on_file)
+    }
+
+    fn into_native<O>(on_file: Self::OnFile<O>, full_input: &'a [u8]) -> Result<Self>
+    where
+        O: ByteOrder,
+    {
+        let ranges = List::from_file(&on_file, full_input)?
+            .typed::<FreeSpaceOnFile<O>>()?
+            .map(|v| v.offset.get() as usize..v.offset.get() as usize + v.length.get() as usize)
+            .collect();
+
+        Ok(FreeMap(ranges))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RegionSource {
+    /// Listed in the file's free space map.
+    FreeMap,
+    /// Not referenced by the prefix, the index, the free map or any indexed chunk.
+    Slack,
+}
+
+impl fmt::Display for RegionSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RegionSource::FreeMap => "free",
+            RegionSource::Slack => "slack",
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Region {
+    pub range: Range<usize>,
+    pub source: RegionSource,
+}
+
+/// A Kauai compressed stream that decoded successfully from an unindexed region.
+pub struct PackedBlob {
+    pub offset: usize,
+    pub codec: [u8; 4],
+    pub data: Vec<u8>,
+}
+
+pub struct Recovery {
+    pub regions: Vec<Region>,
+    pub packed: Vec<PackedBlob>,
+    /// Indexed chunks that Kauai would discard because nothing owns them and they aren't loners.
+    pub orphans: Vec<ChunkId>,
+    pub warnings: Vec<String>,
+}
+
+/// Looks for chunk data that is no longer reachable through the index.
+pub fn scan(file: &ChunkyFile) -> Recovery {
+    let mut warnings = Vec::new();
+
+    let mut free = Vec::new();
+    if !file.free_map_range.is_empty() {
+        match file
+            .data
+            .get(file.free_map_range.clone())
+            .map(FreeMap::load)
+        {
+            Some(Ok(FreeMap(ranges))) => free = ranges,
+            Some(Err(e)) => warnings.push(format!("Unreadable free map: {e:#}")),
+            None => warnings.push("Free map out of bounds".to_owned()),
+        }
+    }
+    free.retain_mut(|r| {
+        r.end = r.end.min(file.data.len());
+        if r.start >= r.end {
+            warnings.push(format!("Free map entry {r:x?} is empty or out of bounds"));
+            false
+        } else {
+            true
+        }
+    });
+    let free = merge(free);
+
+    let mut used = vec![
+        0..mem::size_of::<Prefix<LittleEndian>>(),
+        file.index_range.clone(),
+        file.free_map_range.clone(),
+    ];
+    used.extend(
+        file.index
+            .values()
+            .map(|e| e.offset as usize..e.offset as usize + e.length as usize),
+    );
+    let used = merge(used);
+
+    let mut regions: Vec<_> = free
+        .iter()
+        .map(|r| Region {
+            range: r.clone(),
+            source: RegionSource::FreeMap,
+        })
+        .chain(
+            subtract(0..file.data.len(), &used)
+                .into_iter()
+                .flat_map(|r| subtract(r, &free))
+                .map(|range| Region {
+                    range,
+                    source: RegionSource::Slack,
+                }),
+        )
+        .collect();
+    regions.sort_unstable_by_key(|r| r.range.start);
+
+    let packed = regions
+        .iter()
+        .flat_map(|r| find_packed(file.data, r.range.clone()))
+        .collect();
+
+    let mut orphans: Vec<_> = file
+        .index
+        .keys()
+        .filter(|id| file.is_orphan(id))
+        .copied()
+        .collect();
+    orphans.sort_unstable();
+
+    Recovery {
+        regions,
+        packed,
+        orphans,
+        warnings,
+    }
+}
+
+fn find_packed(data: &[u8], range: Range<usize>) -> Vec<PackedBlob> {
+    let region = &data[range.clone()];
+    let mut found = Vec::new();
+    for (i, window) in region.windows(8).enumerate() {
+        let codec = &window[..4];
+        if codec != b"KCDC" && codec != b"KCD2" {
+            continue;
+        }
+        let remaining = &region[i..];
+        let length = BigEndian::read_u32(&window[4..]) as usize;
+        if length > remaining.len().saturating_mul(MAX_EXPANSION) {
+            continue;
+        }
+        if let Ok(decoded) = kauai::decode(remaining) {
+            found.push(PackedBlob {
+                offset: range.start + i,
+                codec: codec.try_into().unwrap(),
+                data: decoded,
+            });
+        }
+    }
+    found
+}
+
+fn merge(mut ranges: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    ranges.retain(|r| !r.is_empty());
+    ranges.sort_unstable_by_key(|r| r.start);
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+/// Removes sorted, non-overlapping `holes` from `range`.
+fn subtract(range: Range<usize>, holes: &[Range<usize>]) -> Vec<Range<usize>> {
+    let mut result = Vec::new();
+    let mut start = range.start;
+    for hole in holes {
+        if hole.end <= start || hole.start >= range.end {
+            continue;
+        }
+        if hole.start > start {
+            result.push(start..hole.start);
+        }
+        start = start.max(hole.end);
+    }
+    if start < range.end {
+        result.push(start..range.end);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunky::{
+        testing::{chunk, file, id, link},
+        ChunkFlags,
+    };
+
+    #[test]
+    fn subtract_ranges() {
+        assert_eq!(
+            subtract(0..100, &merge(vec![10..20, 15..30, 90..120, 40..40])),
+            vec![0..10, 30..90],
+        );
+    }
+
+    #[test]
+    fn finds_lost_data() {
+        let mut output = file([
+            (
+                "TMPL:1",
+                chunk(ChunkFlags::LONER, "", vec![link("GGCL:3", 0)], b"template"),
+            ),
+            (
+                "GGCL:2",
+                chunk(ChunkFlags::empty(), "", Vec::new(), b"orphan"),
+            ),
+            (
+                "GGCL:3",
+                chunk(ChunkFlags::empty(), "", Vec::new(), b"cells"),
+            ),
+        ]);
+
+        // Move the index along to leave a freed chunk, then some slack holding a deleted
+        // compressed chunk, and put a free map listing only the freed chunk after the index.
+        let field = |output: &[u8], offset| LittleEndian::read_u32(&output[offset..]) as usize;
+        let index_offset = field(&output, 20);
+        let packed = kauai::encode(b"KCDC", b"deleted material").unwrap();
+        let mut lost = vec![0xaa; 16];
+        lost.extend_from_slice(&[0x55; 8]);
+        lost.extend_from_slice(&packed);
+        output.splice(index_offset..index_offset, lost.iter().copied());
+
+        let free_map = output.len();
+        output.extend_from_slice(&[0x01, 0x00, 0x03, 0x03, 0x08, 0x00, 0x00, 0x00]);
+        output.extend_from_slice(&1u32.to_le_bytes());
+        output.extend_from_slice(&(index_offset as u32).to_le_bytes());
+        output.extend_from_slice(&16u32.to_le_bytes());
+        let eof = output.len();
+        for (offset, value) in [
+            (16, eof),
+            (20, index_offset + lost.len()),
+            (28, free_map),
+            (32, eof - free_map),
+        ] {
+            LittleEndian::write_u32(&mut output[offset..], value as u32);
+        }
+
+        let file = ChunkyFile::load(&output).unwrap();
+        let recovery = scan(&file);
+        assert!(recovery.warnings.is_empty(), "{:?}", recovery.warnings);
+        let regions: Vec<_> = recovery
+            .regions
+            .iter()
+            .map(|r| (r.range.clone(), r.source))
+            .collect();
+        assert_eq!(
+            regions,
+            vec![
+                (index_offset..index_offset + 16, RegionSource::FreeMap),
+                (
+                    index_offset + 16..index_offset + lost.len(),
+                    RegionSource::Slack
+                ),
+            ],
+        );
+
+        assert_eq!(recovery.packed.len(), 1);
+        let blob = &recovery.packed[0];
+        assert_eq!(blob.offset, index_offset + 24);
+        assert_eq!(&blob.codec, b"KCDC");
+        assert_eq!(blob.data, b"deleted material");
+
+        assert_eq!(recovery.orphans, vec![id("GGCL:2")]);
+    }
+}
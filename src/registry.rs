@@ -0,0 +1,213 @@
+use std::{
+    fmt,
+    hash::{DefaultHasher, Hash, Hasher},
+};
+
+use crate::{
+    chunky::{ChunkData, ChunkTag, ChunkyFile, IndexEntry},
+    error::Result,
+    ggcl::AnimationCells,
+    ggcm::Costumes,
+    glbs::BodyPartSets,
+    glpi::Armature,
+    glxf::AnimationTransforms,
+    modl::Model,
+    mtrl::Material,
+    order::Loader,
+    tmap::TextureMap,
+    tmpl::Template,
+    txxf::TextureTransform,
+};
+
+/// A chunk decoded according to its tag.
+pub enum TypedChunk<'a> {
+    Template(Template),
+    Armature(Armature),
+    BodyPartSets(BodyPartSets),
+    Costumes(Costumes),
+    Model(Model),
+    Material(Material),
+    TextureMap(TextureMap),
+    TextureTransform(TextureTransform),
+    AnimationCells(AnimationCells),
+    AnimationTransforms(AnimationTransforms),
+    /// A chunk with a tag we don't have a decoder for.
+    Raw(ChunkData<'a>),
+}
+
+impl<'a> TypedChunk<'a> {
+    /// Decodes unpacked chunk data using the decoder registered for `tag`.
+    pub fn decode(tag: ChunkTag, data: ChunkData<'a>) -> Result<Self> {
+        Ok(match &tag.bytes() {
+            b"TMPL" => TypedChunk::Template(Template::load(&data)?),
+            b"GLPI" => TypedChunk::Armature(Armature::load(&data)?),
+            b"GLBS" => TypedChunk::BodyPartSets(BodyPartSets::load(&data)?),
+            b"GGCM" => TypedChunk::Costumes(Costumes::load(&data)?),
+            b"BMDL" => TypedChunk::Model(Model::load(&data)?),
+            b"MTRL" => TypedChunk::Material(Material::load(&data)?),
+            b"TMAP" => TypedChunk::TextureMap(TextureMap::load(&data)?),
+            b"TXXF" => TypedChunk::TextureTransform(TextureTransform::load(&data)?),
+            b"GGCL" => TypedChunk::AnimationCells(AnimationCells::load(&data)?),
+            b"GLXF" => TypedChunk::AnimationTransforms(AnimationTransforms::load(&data)?),
+            _ => TypedChunk::Raw(data),
+        })
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            TypedChunk::Template(_) => "Template",
+            TypedChunk::Armature(_) => "Armature",
+            TypedChunk::BodyPartSets(_) => "BodyPartSets",
+            TypedChunk::Costumes(_) => "Costumes",
+            TypedChunk::Model(_) => "Model",
+            TypedChunk::Material(_) => "Material",
+            TypedChunk::TextureMap(_) => "TextureMap",
+            TypedChunk::TextureTransform(_) => "TextureTransform",
+            TypedChunk::AnimationCells(_) => "AnimationCells",
+            TypedChunk::AnimationTransforms(_) => "AnimationTransforms",
+            TypedChunk::Raw(_) => "Raw",
+        }
+    }
+}
+
+impl TypedChunk<'_> {
+    /// The chunk's contents as `(path, value)` pairs, for comparing chunks field by field.
+    /// Large blocks of bytes are summarized by their length and a hash.
+    pub fn fields(&self) -> Vec<(String, String)> {
+        let mut fields = Vec::new();
+        let mut field = |path: String, value: &dyn fmt::Debug| {
+            fields.push((path, format!("{value:?}")));
+        };
+        match self {
+            TypedChunk::Template(v) => {
+                field("xa_rest".to_owned(), &v.xa_rest);
+                field("ya_rest".to_owned(), &v.ya_rest);
+                field("za_rest".to_owned(), &v.za_rest);
+            }
+            TypedChunk::Armature(v) => {
+                for (i, parent) in v.parents.iter().enumerate() {
+                    field(format!("parents[{i}]"), parent);
+                }
+            }
+            TypedChunk::BodyPartSets(v) => {
+                for (i, group) in v.groups.iter().enumerate() {
+                    field(format!("groups[{i}]"), group);
+                }
+            }
+            TypedChunk::Costumes(v) => {
+                for (i, set) in v.part_sets.iter().enumerate() {
+                    field(format!("part_sets[{i}]"), set);
+                }
+            }
+            TypedChunk::Model(v) => {
+                field("radius".to_owned(), &v.radius);
+                field("bounds".to_owned(), &v.bounds);
+                field("pivot".to_owned(), &v.pivot);
+                for (i, vertex) in v.vertices.iter().enumerate() {
+                    field(format!("vertices[{i}]"), vertex);
+                }
+                for (i, face) in v.faces.iter().enumerate() {
+                    field(format!("faces[{i}]"), face);
+                }
+            }
+            TypedChunk::Material(v) => {
+                field("color".to_owned(), &v.color);
+                field("ambient".to_owned(), &v.ambient);
+                field("diffuse".to_owned(), &v.diffuse);
+                field("specular".to_owned(), &v.specular);
+                field("specular_exponent".to_owned(), &v.specular_exponent);
+            }
+            TypedChunk::TextureMap(v) => {
+                field("width".to_owned(), &v.width);
+                field("height".to_owned(), &v.height);
+                field("data".to_owned(), &BytesSummary(&v.data));
+            }
+            TypedChunk::TextureTransform(v) => {
+                field("min".to_owned(), &v.min);
+                field("max".to_owned(), &v.max);
+            }
+            TypedChunk::AnimationCells(v) => {
+                for (i, cell) in v.cells.iter().enumerate() {
+                    field(format!("cells[{i}].dwr"), &cell.dwr);
+                    for (j, part) in cell.parts.iter().enumerate() {
+                        field(format!("cells[{i}].parts[{j}]"), part);
+                    }
+                }
+            }
+            TypedChunk::AnimationTransforms(v) => {
+                for (i, transform) in v.transforms.iter().enumerate() {
+                    field(format!("transforms[{i}]"), &transform.matrix());
+                }
+            }
+            TypedChunk::Raw(data) => field("data".to_owned(), &BytesSummary(data)),
+        }
+        fields
+    }
+}
+
+struct BytesSummary<'a>(&'a [u8]);
+
+impl fmt::Debug for BytesSummary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut hasher = DefaultHasher::new();
+        self.0.hash(&mut hasher);
+        write!(f, "{} bytes, hash {:016x}", self.0.len(), hasher.finish())
+    }
+}
+
+/// A one line summary of the chunk's contents.
+impl fmt::Display for TypedChunk<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", self.type_name())?;
+        match self {
+            TypedChunk::Template(v) => write!(
+                f,
+                "rest orientation ({}, {}, {})",
+                v.xa_rest, v.ya_rest, v.za_rest,
+            ),
+            TypedChunk::Armature(v) => write!(f, "{} parts", v.parents.len()),
+            TypedChunk::BodyPartSets(v) => write!(f, "{} parts", v.groups.len()),
+            TypedChunk::Costumes(v) => write!(f, "{} part sets", v.part_sets.len()),
+            TypedChunk::Model(v) => {
+                write!(f, "{} vertices, {} faces", v.vertices.len(), v.faces.len())
+            }
+            TypedChunk::Material(v) => write!(f, "color {}", v.color),
+            TypedChunk::TextureMap(v) => write!(f, "{}x{}", v.width, v.height),
+            TypedChunk::TextureTransform(v) => {
+                write!(f, "({}, {})..({}, {})", v.min.x, v.min.y, v.max.x, v.max.y)
+            }
+            TypedChunk::AnimationCells(v) => write!(f, "{} cells", v.cells.len()),
+            TypedChunk::AnimationTransforms(v) => {
+                write!(f, "{} transforms", v.transforms.len())
+            }
+            TypedChunk::Raw(data) => write!(f, "{} bytes", data.len()),
+        }
+    }
+}
+
+impl<'a> ChunkyFile<'a> {
+    /// Unpacks a chunk and decodes it according to its tag.
+    pub fn decode_chunk(&self, entry: &IndexEntry) -> Result<TypedChunk<'a>> {
+        TypedChunk::decode(entry.id.tag, self.get_chunk(entry)?).map_err(|e| e.in_chunk(entry.id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_known_tag_has_a_decoder() {
+        for tag in [
+            "TMPL", "GLPI", "GLBS", "GGCM", "BMDL", "MTRL", "TMAP", "TXXF", "GGCL", "GLXF",
+        ] {
+            // A registered tag is claimed by its decoder, which rejects empty data.
+            assert!(
+                TypedChunk::decode(tag.parse().unwrap(), ChunkData::Borrowed(&[])).is_err(),
+                "{tag}",
+            );
+        }
+        let raw = TypedChunk::decode("ZZZZ".parse().unwrap(), ChunkData::Borrowed(b"abc")).unwrap();
+        assert_eq!(raw.to_string(), "Raw: 3 bytes");
+    }
+}
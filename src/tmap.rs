@@ -1,10 +1,12 @@
 use std::mem;
 
-use anyhow::{ensure, Result};
 use byteorder::ByteOrder;
 use zerocopy::{FromBytes, U16};
 
-use crate::order::Loader;
+use crate::{
+    error::{Error, ErrorKind, Result},
+    order::Loader,
+};
 
 #[derive(FromBytes)]
 #[repr(C)]
@@ -51,32 +53,40 @@ impl<'a> Loader<'a> for TextureMap {
         // Why'd it have to be this one?
         // All textures in the base game tmpls.3cn are indexed color.
         // At least it's a multiple of eight bits and it's not YUV.
-        ensure!(
-            on_file.r#type == BR_PMT_INDEX_8,
-            "Unsupported texture format {}",
-            on_file.r#type,
-        );
+        if on_file.r#type != BR_PMT_INDEX_8 {
+            return Err(Error::new(
+                ErrorKind::UnsupportedPixelType(on_file.r#type),
+                mem::offset_of!(TextureMapOnFile<O>, r#type),
+            ));
+        }
 
         // I'm not sure what these are supposed to mean.
         // If they're always zero I don't need to find out.
-        ensure!(
-            on_file.base_x.get() == 0
-                && on_file.base_y.get() == 0
-                && on_file.origin_x.get() == 0
-                && on_file.origin_y.get() == 0,
-            "Unsupported texture origin",
-        );
-
-        assert_eq!(on_file.stride.get(), on_file.width.get());
+        if on_file.base_x.get() != 0
+            || on_file.base_y.get() != 0
+            || on_file.origin_x.get() != 0
+            || on_file.origin_y.get() != 0
+        {
+            return Err(Error::unsupported(
+                "texture origin",
+                mem::offset_of!(TextureMapOnFile<O>, base_x),
+            ));
+        }
 
-        let mut data =
-            Vec::with_capacity(on_file.width.get() as usize * on_file.height.get() as usize);
-        let mut input = &full_input[mem::size_of::<TextureMapOnFile<O>>()..];
-        for input_row in input.chunks_exact(on_file.stride.get() as usize) {
-            data.extend_from_slice(&input_row[..on_file.width.get() as usize]);
-            input = &input[on_file.stride.get() as usize - on_file.width.get() as usize..];
+        if on_file.stride.get() != on_file.width.get() {
+            return Err(Error::unsupported(
+                "texture stride",
+                mem::offset_of!(TextureMapOnFile<O>, stride),
+            ));
         }
 
+        let size = on_file.width.get() as usize * on_file.height.get() as usize;
+        let header = mem::size_of::<TextureMapOnFile<O>>();
+        let Some(input) = full_input.get(header..header + size) else {
+            return Err(Error::truncated("texture data", full_input.len()));
+        };
+        let data = input.to_vec();
+
         Ok(TextureMap {
             width: on_file.width.get(),
             height: on_file.height.get(),
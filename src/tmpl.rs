@@ -1,8 +1,7 @@
-use anyhow::Result;
 use byteorder::ByteOrder;
 use zerocopy::{FromBytes, U16, U32};
 
-use crate::{brender::UFraction, order::Loader};
+use crate::{brender::UFraction, error::Result, order::Loader};
 
 #[derive(FromBytes)]
 #[repr(C)]
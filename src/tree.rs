@@ -0,0 +1,333 @@
+use std::{
+    borrow::Cow,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::{bail, Context, Result};
+use encoding_rs::Encoding;
+use serde_json::{json, Value};
+use zerocopy::U32;
+
+use crate::{
+    chunky::{
+        is_unicode_osk, ChildLink, ChunkFlags, ChunkId, ChunkTag, ChunkyFile, ChunkyWriter,
+        IndexEntry, NewChunk,
+    },
+    kauai::{self, Level},
+};
+
+/// The file at the top of an extracted tree describing the chunky file itself.
+pub const FILE_SIDECAR: &str = "chunky.json";
+
+/// The directory a chunk's data and sidecar are written to.
+/// Tags can contain spaces and punctuation, so anything other than letters and digits is
+/// written as `_` followed by its hex value.
+pub fn tag_directory(tag: ChunkTag) -> String {
+    let mut directory = String::new();
+    for b in tag.bytes() {
+        if b.is_ascii_alphanumeric() {
+            directory.push(b as char);
+        } else {
+            directory.push_str(&format!("_{b:02x}"));
+        }
+    }
+    directory
+}
+
+fn chunk_sidecar(entry: &IndexEntry, codec: Option<&[u8]>, damage: Option<&str>) -> Value {
+    json!({
+        "tag": entry.id.tag.to_string(),
+        "number": entry.id.number.get(),
+        "name": entry.name,
+        // The name's string kind, so a name stored as UTF-16 is written that way again.
+        "name_osk": entry.name_osk,
+        "flags": entry.flags.iter_names().map(|(name, _)| name).collect::<Vec<_>>(),
+        "codec": codec.map(|c| c.escape_ascii().to_string()),
+        // False if the chunk is packed but couldn't be decoded, so the data is written as is.
+        "decoded": damage.is_none(),
+        "damage": damage,
+        "children": entry
+            .children
+            .iter()
+            .map(|c| json!({
+                "tag": c.chunk_id.tag.to_string(),
+                "number": c.chunk_id.number.get(),
+                "child_id": c.child_id,
+            }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+/// Writes each of `entries` to `TAG/number.bin` under `output`, unpacked, with its index entry in
+/// `TAG/number.json`. Returns the chunks that were packed but couldn't be unpacked; these are
+/// written exactly as stored, with as much as could be unpacked in `TAG/number.partial.bin`.
+pub fn extract(file: &ChunkyFile, entries: &[&IndexEntry], output: &Path) -> Result<Vec<ChunkId>> {
+    fs::create_dir_all(output).with_context(|| format!("Creating {}", output.display()))?;
+    fs::write(
+        output.join(FILE_SIDECAR),
+        serde_json::to_string_pretty(&json!({ "creator": file.creator.to_string() }))?,
+    )?;
+
+    let mut undecoded = Vec::new();
+    for entry in entries {
+        let id = &entry.id;
+        let raw = file.raw_chunk(entry)?;
+        let codec = entry
+            .flags
+            .contains(ChunkFlags::PACKED)
+            .then(|| raw.get(..4))
+            .flatten();
+
+        let directory = output.join(tag_directory(id.tag));
+        fs::create_dir_all(&directory)
+            .with_context(|| format!("Creating {}", directory.display()))?;
+        let number = id.number.get();
+        let bin = directory.join(format!("{number}.bin"));
+        let damage = if codec.is_some() {
+            unpack_to(raw, &bin)?
+        } else {
+            fs::write(&bin, raw)?;
+            None
+        };
+        if damage.is_some() {
+            undecoded.push(*id);
+            fs::rename(&bin, bin.with_extension("partial.bin"))?;
+            fs::write(&bin, raw)?;
+        }
+        fs::write(
+            directory.join(format!("{number}.json")),
+            serde_json::to_string_pretty(&chunk_sidecar(entry, codec, damage.as_deref()))?,
+        )?;
+    }
+    Ok(undecoded)
+}
+
+/// Unpacks a packed chunk straight into the file at `path`, so large chunks aren't held in memory.
+/// If the chunk is damaged, returns what's wrong with it, leaving the data before the damage in
+/// the file.
+fn unpack_to(packed: &[u8], path: &Path) -> Result<Option<String>> {
+    let mut output = BufWriter::new(File::create(path)?);
+    let damage = match kauai::Decoder::new(packed) {
+        Ok(mut decoder) => match io::copy(&mut decoder, &mut output) {
+            Ok(_) => None,
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => Some(e.to_string()),
+            Err(e) => return Err(e.into()),
+        },
+        Err(error) => Some(error.to_string()),
+    };
+    output.flush()?;
+    Ok(damage)
+}
+
+/// Reads a tree written by [`extract`] back in, packing chunks flagged PACKED again with the
+/// codec recorded in their sidecar at the given level. Single byte names are written in
+/// `code_page`, which should be the one the tree was extracted with.
+pub fn rebuild(
+    input: &Path,
+    level: Level,
+    code_page: &'static Encoding,
+) -> Result<ChunkyWriter<'static>> {
+    let file: Value = serde_json::from_slice(
+        &fs::read(input.join(FILE_SIDECAR))
+            .with_context(|| format!("Reading {}", input.join(FILE_SIDECAR).display()))?,
+    )?;
+    let creator = file["creator"]
+        .as_str()
+        .context("Missing creator")?
+        .parse()?;
+
+    let mut writer = ChunkyWriter::new(creator).with_code_page(code_page);
+    for directory in fs::read_dir(input)? {
+        let directory = directory?.path();
+        if !directory.is_dir() {
+            continue;
+        }
+        for sidecar in fs::read_dir(&directory)? {
+            let sidecar = sidecar?.path();
+            if sidecar.extension().is_none_or(|e| e != "json") {
+                continue;
+            }
+            let (id, chunk) = read_chunk(&sidecar, level, code_page)
+                .with_context(|| format!("Reading {}", sidecar.display()))?;
+            if writer.insert(id, chunk).is_some() {
+                bail!("Duplicate chunk {id} in {}", sidecar.display());
+            }
+        }
+    }
+    Ok(writer)
+}
+
+fn read_chunk(
+    sidecar: &Path,
+    level: Level,
+    code_page: &'static Encoding,
+) -> Result<(ChunkId, NewChunk<'static>)> {
+    let metadata: Value = serde_json::from_slice(&fs::read(sidecar)?)?;
+    let id = read_id(&metadata)?;
+
+    let mut flags = ChunkFlags::empty();
+    for name in metadata["flags"].as_array().context("Missing flags")? {
+        let name = name.as_str().context("Invalid flag")?;
+        flags |= ChunkFlags::from_name(name).with_context(|| format!("Unknown flag {name}"))?;
+    }
+
+    let children = metadata["children"]
+        .as_array()
+        .context("Missing children")?
+        .iter()
+        .map(|child| {
+            Ok(ChildLink {
+                chunk_id: read_id(child)?,
+                child_id: child["child_id"]
+                    .as_u64()
+                    .and_then(|v| u32::try_from(v).ok())
+                    .context("Invalid child id")?,
+            })
+        })
+        .collect::<Result<_>>()?;
+
+    let name = metadata["name"].as_str().unwrap_or_default().to_owned();
+    // Trees from before the string kind was recorded held single byte names.
+    let unicode_name = metadata["name_osk"]
+        .as_u64()
+        .and_then(|osk| u16::try_from(osk).ok())
+        .is_some_and(is_unicode_osk);
+    if !unicode_name && code_page.encode(&name).2 {
+        bail!(
+            "{id} name {name:?} can't be written in {}; rebuild with the code page the tree was \
+             extracted with",
+            code_page.name(),
+        );
+    }
+
+    let mut data = fs::read(sidecar.with_extension("bin"))?;
+    if flags.contains(ChunkFlags::PACKED) && metadata["decoded"].as_bool().unwrap_or(true) {
+        let codec = metadata["codec"].as_str().unwrap_or("KCDC");
+        data = kauai::encode_with_level(codec.as_bytes(), &data, level)?;
+    }
+
+    Ok((
+        id,
+        NewChunk {
+            flags,
+            name: Cow::Owned(name),
+            unicode_name,
+            children,
+            data: Cow::Owned(data),
+        },
+    ))
+}
+
+fn read_id(value: &Value) -> Result<ChunkId> {
+    let tag = value["tag"].as_str().context("Missing tag")?.parse()?;
+    let number = value["number"]
+        .as_u64()
+        .and_then(|n| u32::try_from(n).ok())
+        .context("Invalid chunk number")?;
+    Ok(ChunkId {
+        tag,
+        number: U32::new(number),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use encoding_rs::{WINDOWS_1251, WINDOWS_1252};
+
+    use super::*;
+    use crate::{
+        chunky::testing::{chunk, id, link, write, writer},
+        order::Loader,
+    };
+
+    #[test]
+    fn extract_and_rebuild() {
+        let texture: Vec<u8> = (0..5000).map(|i| (i * 7 % 256) as u8).collect();
+        let material = kauai::encode(b"KCDC", b"material").unwrap();
+        let packed_texture = kauai::encode(b"KCD2", &texture).unwrap();
+        let mut damaged = kauai::encode(b"KCDC", b"damaged material").unwrap();
+        damaged.truncate(damaged.len() - 5);
+        let mut writer = writer().with_code_page(WINDOWS_1251);
+        for (chunk_id, chunk) in [
+            (
+                "MTRL:1",
+                NewChunk {
+                    unicode_name: true,
+                    ..chunk(
+                        ChunkFlags::LONER | ChunkFlags::PACKED,
+                        "Shiny",
+                        vec![link("TMAP:2", 0)],
+                        &material,
+                    )
+                },
+            ),
+            (
+                "TMAP:2",
+                chunk(ChunkFlags::PACKED, "", Vec::new(), &packed_texture),
+            ),
+            (
+                "GST :3",
+                chunk(ChunkFlags::LONER, "Ключ", Vec::new(), b"strings"),
+            ),
+            (
+                "MTRL:4",
+                chunk(
+                    ChunkFlags::LONER | ChunkFlags::PACKED,
+                    "",
+                    Vec::new(),
+                    &damaged,
+                ),
+            ),
+        ] {
+            writer.insert(id(chunk_id), chunk);
+        }
+        let original = write(&writer);
+
+        let directory = std::env::temp_dir().join(format!("3dmm-dump-tree-{}", std::process::id()));
+        let original = ChunkyFile::load(&original)
+            .unwrap()
+            .with_code_page(WINDOWS_1251);
+        let entries: Vec<_> = original.index.values().collect();
+        assert_eq!(
+            extract(&original, &entries, &directory).unwrap(),
+            vec![id("MTRL:4")]
+        );
+        let partial = fs::read(directory.join("MTRL/4.partial.bin")).unwrap();
+        assert!(b"damaged material".starts_with(&partial) && !partial.is_empty());
+        // The Cyrillic name only fits the code page it was extracted with.
+        assert!(rebuild(&directory, Level::default(), WINDOWS_1252).is_err());
+        let mut rebuilt = Vec::new();
+        rebuild(&directory, Level::default(), WINDOWS_1251)
+            .unwrap()
+            .write_to(&mut rebuilt)
+            .unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        let rebuilt = ChunkyFile::load(&rebuilt)
+            .unwrap()
+            .with_code_page(WINDOWS_1251);
+        assert_eq!(rebuilt.creator, "CHMP");
+        assert_eq!(rebuilt.index.len(), 4);
+        for (id, entry) in &original.index {
+            let copy = &rebuilt.index[id];
+            assert_eq!(copy.flags, entry.flags);
+            assert_eq!(copy.name, entry.name);
+            assert_eq!(copy.name_osk, entry.name_osk);
+            assert_eq!(copy.children.len(), entry.children.len());
+            if *id == self::id("MTRL:4") {
+                assert_eq!(rebuilt.raw_chunk(copy).unwrap(), &damaged[..]);
+                continue;
+            }
+            assert_eq!(
+                rebuilt.get_chunk(copy).unwrap()[..],
+                original.get_chunk(entry).unwrap()[..],
+            );
+        }
+        assert_eq!(
+            rebuilt.raw_chunk(&rebuilt.index[&id("TMAP:2")]).unwrap()[..4],
+            *b"KCD2"
+        );
+    }
+}
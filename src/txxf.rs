@@ -1,9 +1,8 @@
-use anyhow::Result;
 use byteorder::ByteOrder;
 use nalgebra::{point, Affine2, Matrix3, Point2};
 use zerocopy::{FromBytes, U16};
 
-use crate::{brender::Scalar, order::Loader};
+use crate::{brender::Scalar, error::Result, order::Loader};
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct TextureTransform {